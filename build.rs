@@ -7,8 +7,12 @@ use std::{
     io::{BufWriter, Write},
 };
 
-use crate::config::{Config, DatabaseConfig, DatabaseType, TableConfig};
+use crate::config::{
+    ColumnOptions, Config, DatabaseConfig, DatabaseType, DateTruncation, MaskingRule, Partition,
+    TableConfig,
+};
 use schemars::schema_for_value;
+use std::collections::HashMap;
 
 fn main() {
     let database = DatabaseConfig {
@@ -17,17 +21,53 @@ fn main() {
         database: "test_database".to_string(),
         user: "testuser".to_string(),
         password: "passw0rd!".to_string(),
+        snapshot: false,
+        include: vec!["order_*".to_string()],
+        exclude: vec!["/_(tmp|bak)$/".to_string()],
     };
 
-    let columns = vec![];
+    let columns = vec!["*".to_string()];
+
+    let mut masking = HashMap::new();
+    masking.insert(
+        "email".to_string(),
+        MaskingRule::FakeEmail {
+            salt: "change me".to_string(),
+        },
+    );
+
+    let mut column_options = HashMap::new();
+    column_options.insert(
+        "created_at".to_string(),
+        ColumnOptions {
+            as_json: false,
+            date_format: Some("iso8601".to_string()),
+            source_timezone: Some("Europe/Berlin".to_string()),
+        },
+    );
 
     let tables = vec![TableConfig {
         columns,
         name: "some_table".to_string(),
         where_clause: Some("where 1=1".to_string()),
+        masking,
+        pseudonymize: vec!["customer_id".to_string()],
+        query: None,
+        binary_files: false,
+        spatial_columns: vec!["delivery_zone".to_string()],
+        column_options,
+        partition_by: vec![Partition {
+            column: "created_at".to_string(),
+            truncate: Some(DateTruncation::Month),
+        }],
+        output_name: Some("{database}/{table}_{run_id}.{ext}".to_string()),
     }];
 
-    let config = Config { database, tables };
+    let config = Config {
+        database,
+        tables,
+        follow_foreign_keys: false,
+    };
 
     let schema = schema_for_value!(config);
 
@@ -0,0 +1,88 @@
+use indexmap::IndexMap;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::data_types::BinaryFile;
+
+/// Writes binary values to `<dir>/<table>/<sha256>.bin`, so identical values
+/// are stored once
+pub struct BinaryFiles {
+    dir: PathBuf,
+    /// Files of each table by path, for the manifest
+    stored: Mutex<HashMap<String, IndexMap<String, BinaryFile>>>,
+}
+
+impl BinaryFiles {
+    pub fn new(dir: PathBuf) -> BinaryFiles {
+        BinaryFiles {
+            dir,
+            stored: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Files stored for `table` since the last call, each once
+    pub fn take_stored(&self, table: &str) -> Vec<BinaryFile> {
+        let mut stored = self.stored.lock().unwrap();
+        stored
+            .remove(table)
+            .map(|files| files.into_iter().map(|(_, file)| file).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn store(&self, table: &str, bytes: &[u8]) -> anyhow::Result<BinaryFile> {
+        let sha256 = hex::encode(Sha256::digest(bytes));
+        let file_name = format!("{}.bin", sha256);
+
+        let table_dir = self.dir.join(table);
+        fs::create_dir_all(&table_dir)?;
+        let path = table_dir.join(&file_name);
+        if !path.exists() {
+            // renamed once complete, as existing files are not written again
+            let temp_path = table_dir.join(format!(".{}.tmp", file_name));
+            fs::write(&temp_path, bytes)?;
+            fs::rename(&temp_path, &path)?;
+        }
+
+        let file = BinaryFile {
+            path: format!("{}/{}", table, file_name),
+            sha256,
+            size: bytes.len(),
+        };
+        let mut stored = self.stored.lock().unwrap();
+        stored
+            .entry(table.to_string())
+            .or_default()
+            .insert(file.path.clone(), file.clone());
+
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stores_value_under_its_hash() {
+        let dir = std::env::temp_dir().join("db-extractor-binary-files-test");
+        let files = BinaryFiles::new(dir.clone());
+
+        let file = files.store("images", b"abc").unwrap();
+        files.store("images", b"abc").unwrap();
+
+        assert_eq!(
+            file.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(file.path, format!("images/{}.bin", file.sha256));
+        assert_eq!(file.size, 3);
+        assert_eq!(fs::read(dir.join(&file.path)).unwrap(), b"abc");
+        assert_eq!(files.take_stored("images").len(), 1);
+        assert!(files.take_stored("images").is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
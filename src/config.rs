@@ -1,10 +1,15 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 #[derive(Deserialize, Debug, JsonSchema, Serialize)]
 pub struct Config {
     pub tables: Vec<TableConfig>,
     pub database: DatabaseConfig,
+    /// Use the where clauses as seeds of a subset and extract the rows of all
+    /// other tables related to them by foreign keys
+    #[serde(default)]
+    pub follow_foreign_keys: bool,
 }
 
 #[derive(Deserialize, Debug, JsonSchema, Serialize)]
@@ -15,14 +20,103 @@ pub struct DatabaseConfig {
     pub database: String,
     #[serde(alias = "type")]
     pub database_type: DatabaseType,
+    /// Run the whole extraction inside a single snapshot so that all tables
+    /// reflect the same point in time.
+    #[serde(default)]
+    pub snapshot: bool,
+    /// Glob or `/regex/` patterns of tables to extract in addition to the
+    /// configured tables, with all of their columns
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Patterns of tables to leave out of `include`
+    #[serde(default)]
+    pub exclude: Vec<String>,
 }
 
-#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+#[derive(Deserialize, Debug, JsonSchema, Serialize, Clone, Default)]
 pub struct TableConfig {
+    /// Name of the table, or only of the output if `query` is set
     pub name: String,
+    /// `*` expands to all columns of the table
+    #[serde(default)]
     pub columns: Vec<String>,
+    /// Query used instead of selecting `columns` from the table. The columns
+    /// of the output are the columns of its result.
+    pub query: Option<String>,
+    /// Filter of the rows, not allowed together with `query`
     #[serde(alias = "where")]
     pub where_clause: Option<String>,
+    /// Masking rules by column name, applied before values are written.
+    #[serde(default)]
+    pub masking: HashMap<String, MaskingRule>,
+    /// Columns replaced by keyed tokens that stay the same across tables and runs
+    #[serde(default)]
+    pub pseudonymize: Vec<String>,
+    /// Write binary values to files in a directory named after the table and
+    /// output their relative path and hash instead of the bytes
+    #[serde(default)]
+    pub binary_files: bool,
+    /// Columns with a spatial type, which are written as WKT or GeoJSON.
+    /// Detected from the schema of the table, columns of a `query` have to be
+    /// listed and selected as WKT by the query.
+    #[serde(default)]
+    pub spatial_columns: Vec<String>,
+    /// Output options by column name
+    #[serde(default)]
+    pub column_options: HashMap<String, ColumnOptions>,
+    /// Columns splitting the rows into directories like
+    /// `table/region=eu/created_at_month=2021-01/part.json`
+    #[serde(default)]
+    pub partition_by: Vec<Partition>,
+    /// File name template of the table, instead of the one of the command line
+    pub output_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, JsonSchema, Serialize, Clone)]
+pub struct Partition {
+    pub column: String,
+    /// Partition dates by their year, month or day instead of their value
+    pub truncate: Option<DateTruncation>,
+}
+
+#[derive(Deserialize, Debug, JsonSchema, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DateTruncation {
+    Year,
+    Month,
+    Day,
+}
+
+#[derive(Deserialize, Debug, JsonSchema, Serialize, Clone, Default)]
+pub struct ColumnOptions {
+    /// The text of the column is JSON and written as nested value in json
+    /// output instead of as string
+    #[serde(default)]
+    pub as_json: bool,
+    /// Format of date and time values, overriding the global one
+    pub date_format: Option<String>,
+    /// Time zone of datetimes without one, overriding the global one
+    pub source_timezone: Option<String>,
+}
+
+#[derive(Deserialize, Debug, JsonSchema, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskingRule {
+    /// SHA-256 of the salted value, hex encoded
+    Hash { salt: String },
+    /// Replaces the value with a fixed text
+    Redact { replacement: Option<String> },
+    Nullify,
+    /// Keeps only the first `length` characters
+    Truncate { length: usize },
+    /// Fake values keeping the shape of the original, derived from it and the
+    /// secret salt so that equal inputs give equal outputs, but the original
+    /// can not be found by trying candidates without the salt
+    FakeEmail { salt: String },
+    FakeName { salt: String },
+    FakePhone { salt: String },
+    /// Moves dates and datetimes by the given number of days
+    DateShift { days: i64 },
 }
 
 #[derive(Deserialize, Debug, JsonSchema, Serialize)]
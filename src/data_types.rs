@@ -1,10 +1,16 @@
-use serde::{Serialize, Serializer};
+use anyhow::bail;
+use serde::{ser::Error, Deserialize, Serialize, Serializer};
+use serde_json::value::RawValue;
+use std::convert::TryFrom;
 use sqlx::types::Uuid;
+use std::str::FromStr;
 use tiberius::{
     numeric::Decimal,
     time::chrono::{self, Utc},
 };
 
+use crate::spatial::wkt_to_geojson;
+
 #[derive(Debug)]
 pub enum DataType {
     String(Option<String>),
@@ -19,6 +25,53 @@ pub enum DataType {
     DateTime(Option<chrono::NaiveDateTime>),
     Date(Option<chrono::NaiveDate>),
     Time(Option<chrono::NaiveTime>),
+    Binary(Option<Vec<u8>>),
+    BinaryFile(Option<BinaryFile>),
+    /// Spatial value as WKT
+    Geometry(Option<String>),
+    /// JSON document, written as nested value in its key order
+    Json(Option<serde_json::Value>),
+}
+
+/// Representation of binary values written inline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryEncoding {
+    Base64,
+    Hex,
+    /// `0x...` literal, only unquoted in sql output
+    Literal,
+}
+
+impl FromStr for BinaryEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base64" => Ok(Self::Base64),
+            "hex" => Ok(Self::Hex),
+            "literal" => Ok(Self::Literal),
+            _ => bail!("binary encoding can only be base64, hex or literal"),
+        }
+    }
+}
+
+impl BinaryEncoding {
+    pub fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Base64 => base64::encode(bytes),
+            Self::Hex => hex::encode(bytes),
+            Self::Literal => format!("0x{}", hex::encode_upper(bytes)),
+        }
+    }
+}
+
+/// Reference to a binary value written to its own file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinaryFile {
+    /// Path relative to the output directory
+    pub path: String,
+    pub sha256: String,
+    pub size: usize,
 }
 
 impl Serialize for DataType {
@@ -65,7 +118,10 @@ impl Serialize for DataType {
             }
             Self::Decimal(decimal) => {
                 if let Some(decimal) = decimal {
-                    serializer.serialize_f64(decimal.to_string().parse().unwrap())
+                    // written as is, a f64 loses digits of large values like ids
+                    RawValue::from_string(decimal.to_string())
+                        .map_err(S::Error::custom)?
+                        .serialize(serializer)
                 } else {
                     serializer.serialize_none()
                 }
@@ -112,6 +168,161 @@ impl Serialize for DataType {
                     serializer.serialize_none()
                 }
             }
+            Self::Binary(bytes) => {
+                if let Some(bytes) = bytes {
+                    serializer.serialize_str(&hex::encode(bytes))
+                } else {
+                    serializer.serialize_none()
+                }
+            }
+            Self::BinaryFile(file) => {
+                if let Some(file) = file {
+                    file.serialize(serializer)
+                } else {
+                    serializer.serialize_none()
+                }
+            }
+            Self::Json(json) => {
+                if let Some(json) = json {
+                    json.serialize(serializer)
+                } else {
+                    serializer.serialize_none()
+                }
+            }
+            Self::Geometry(wkt) => {
+                if let Some(wkt) = wkt {
+                    wkt_to_geojson(wkt)
+                        .map_err(S::Error::custom)?
+                        .serialize(serializer)
+                } else {
+                    serializer.serialize_none()
+                }
+            }
         }
     }
 }
+
+impl DataType {
+    /// Unsigned 64 bit integer, as decimal if it does not fit into a `BigInt`
+    pub fn unsigned(value: Option<u64>) -> DataType {
+        match value {
+            Some(value) => match i64::try_from(value) {
+                Ok(value) => Self::BigInt(Some(value)),
+                Err(_) => Self::Decimal(Some(Decimal::from(value))),
+            },
+            None => Self::BigInt(None),
+        }
+    }
+
+    /// Textual form of the value, `None` if it is null
+    pub fn to_text(&self) -> Option<String> {
+        match self {
+            Self::String(str) => str.clone(),
+            Self::Int(int) => int.map(|int| int.to_string()),
+            Self::BigInt(int) => int.map(|int| int.to_string()),
+            Self::Float(float) => float.map(|float| float.to_string()),
+            Self::Double(double) => double.map(|double| double.to_string()),
+            Self::Decimal(decimal) => decimal.map(|decimal| decimal.to_string()),
+            Self::Bool(b) => b.map(|b| b.to_string()),
+            Self::Uuid(uuid) => uuid.map(|uuid| uuid.to_string()),
+            Self::DateTimeUtc(datetime) => datetime.map(|datetime| datetime.to_string()),
+            Self::DateTime(datetime) => datetime.map(|datetime| datetime.to_string()),
+            Self::Date(date) => date.map(|date| date.to_string()),
+            Self::Time(time) => time.map(|time| time.to_string()),
+            Self::Binary(bytes) => bytes.as_ref().map(hex::encode),
+            Self::BinaryFile(file) => file.as_ref().map(|file| file.path.clone()),
+            Self::Geometry(wkt) => wkt.clone(),
+            Self::Json(json) => json.as_ref().map(|json| json.to_string()),
+        }
+    }
+
+    /// Inline binary values as text in the given encoding
+    pub fn encode_binary(self, encoding: BinaryEncoding) -> DataType {
+        match self {
+            Self::Binary(Some(bytes)) => Self::String(Some(encoding.encode(&bytes))),
+            value => value,
+        }
+    }
+
+    /// Spatial values are selected as WKT, which arrives as text
+    pub fn into_geometry(self) -> DataType {
+        match self {
+            Self::String(wkt) => Self::Geometry(wkt),
+            Self::Binary(wkt) => {
+                Self::Geometry(wkt.map(|wkt| String::from_utf8_lossy(&wkt).into_owned()))
+            }
+            value => value,
+        }
+    }
+
+    /// JSON document from its text, which is parsed only here
+    pub fn json(text: Option<String>) -> serde_json::Result<DataType> {
+        Ok(Self::Json(text.map(|text| serde_json::from_str(&text)).transpose()?))
+    }
+
+    /// Text flagged as JSON by the column options
+    pub fn into_json(self) -> serde_json::Result<DataType> {
+        match self {
+            Self::String(json) => Self::json(json),
+            value => Ok(value),
+        }
+    }
+
+    /// Null value of the same type
+    pub fn to_null(&self) -> DataType {
+        match self {
+            Self::String(_) => Self::String(None),
+            Self::Int(_) => Self::Int(None),
+            Self::BigInt(_) => Self::BigInt(None),
+            Self::Float(_) => Self::Float(None),
+            Self::Double(_) => Self::Double(None),
+            Self::Decimal(_) => Self::Decimal(None),
+            Self::Bool(_) => Self::Bool(None),
+            Self::Uuid(_) => Self::Uuid(None),
+            Self::DateTimeUtc(_) => Self::DateTimeUtc(None),
+            Self::DateTime(_) => Self::DateTime(None),
+            Self::Date(_) => Self::Date(None),
+            Self::Time(_) => Self::Time(None),
+            Self::Binary(_) => Self::Binary(None),
+            Self::BinaryFile(_) => Self::BinaryFile(None),
+            Self::Geometry(_) => Self::Geometry(None),
+            Self::Json(_) => Self::Json(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_is_written_as_nested_value() {
+        let nested = DataType::json(Some(
+            r#"{"tags": ["a", "b"], "size": {"x": 1}}"#.to_string(),
+        ))
+        .unwrap();
+        assert_eq!(
+            serde_json::to_string(&nested).unwrap(),
+            r#"{"tags":["a","b"],"size":{"x":1}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&DataType::Json(None)).unwrap(),
+            "null"
+        );
+        assert!(DataType::json(Some("{".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_large_integers_keep_their_digits() {
+        let id = 1_234_567_890_123_456_789;
+        assert!(id > 1 << 53);
+
+        assert!(matches!(DataType::unsigned(Some(id)), DataType::BigInt(Some(_))));
+        let values = [DataType::unsigned(Some(id)), DataType::unsigned(Some(u64::MAX))];
+        assert_eq!(
+            serde_json::to_string(&values).unwrap(),
+            "[1234567890123456789,18446744073709551615]"
+        );
+        assert_eq!(serde_json::to_string(&DataType::unsigned(None)).unwrap(), "null");
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::{anyhow, bail};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::config::ColumnOptions;
+use crate::data_types::DataType;
+
+/// Output format of date and time values
+#[derive(Debug, Clone, PartialEq)]
+pub enum DateFormat {
+    /// Text form of chrono, like `2021-01-01 10:00:00 UTC`
+    Text,
+    /// ISO 8601, datetimes with time zone as RFC 3339
+    Iso8601,
+    EpochSeconds,
+    EpochMillis,
+    /// strftime pattern like `%d.%m.%Y`
+    Pattern(String),
+}
+
+impl FromStr for DateFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "iso8601" => Ok(Self::Iso8601),
+            "epoch_seconds" => Ok(Self::EpochSeconds),
+            "epoch_millis" => Ok(Self::EpochMillis),
+            _ if s.contains('%') => {
+                if StrftimeItems::new(s).any(|item| item == Item::Error) {
+                    bail!("invalid date pattern {}", s);
+                }
+                Ok(Self::Pattern(s.to_string()))
+            }
+            _ => bail!(
+                "date format can only be text, iso8601, epoch_seconds, epoch_millis \
+                 or a strftime pattern"
+            ),
+        }
+    }
+}
+
+pub fn parse_timezone(name: &str) -> anyhow::Result<Tz> {
+    name.parse().map_err(|err| anyhow!("{}", err))
+}
+
+/// How date and time values of a column are written
+#[derive(Debug, Clone)]
+pub struct DateOptions {
+    pub format: DateFormat,
+    /// Time zone of datetimes without one, which are converted to UTC
+    pub source_timezone: Option<Tz>,
+}
+
+impl DateOptions {
+    /// Options set for a column, the remaining ones taken from `self`
+    pub fn for_column(&self, options: &ColumnOptions) -> anyhow::Result<DateOptions> {
+        Ok(DateOptions {
+            format: match &options.date_format {
+                Some(format) => format.parse()?,
+                None => self.format.clone(),
+            },
+            source_timezone: match &options.source_timezone {
+                Some(timezone) => Some(parse_timezone(timezone)?),
+                None => self.source_timezone,
+            },
+        })
+    }
+
+    pub fn to_utc(&self, value: DataType) -> anyhow::Result<DataType> {
+        match (value, &self.source_timezone) {
+            (DataType::DateTime(Some(datetime)), Some(timezone)) => {
+                let local = timezone.from_local_datetime(&datetime).earliest().ok_or_else(|| {
+                    anyhow!("{} does not exist in time zone {}", datetime, timezone)
+                })?;
+                Ok(DataType::DateTimeUtc(Some(local.with_timezone(&Utc))))
+            }
+            (value, _) => Ok(value),
+        }
+    }
+
+    /// Datetimes without time zone are taken as UTC for epoch values. Times
+    /// have no epoch and are written as ISO 8601 then.
+    pub fn format(&self, value: DataType) -> anyhow::Result<DataType> {
+        let pattern = match &self.format {
+            DateFormat::Text => return Ok(value),
+            DateFormat::Iso8601 => return Ok(iso8601(value)),
+            DateFormat::EpochSeconds => {
+                return Ok(match epoch_datetime(&value) {
+                    Some(datetime) => DataType::BigInt(Some(datetime.timestamp())),
+                    None => iso8601(value),
+                })
+            }
+            DateFormat::EpochMillis => {
+                return Ok(match epoch_datetime(&value) {
+                    Some(datetime) => DataType::BigInt(Some(datetime.timestamp_millis())),
+                    None => iso8601(value),
+                })
+            }
+            DateFormat::Pattern(pattern) => pattern,
+        };
+
+        let text = match &value {
+            DataType::DateTimeUtc(Some(datetime)) => format_pattern(datetime.format(pattern))?,
+            DataType::DateTime(Some(datetime)) => format_pattern(datetime.format(pattern))?,
+            DataType::Date(Some(date)) => format_pattern(date.and_hms(0, 0, 0).format(pattern))?,
+            DataType::Time(Some(time)) => format_pattern(time.format(pattern))?,
+            _ => return Ok(value),
+        };
+
+        Ok(DataType::String(Some(text)))
+    }
+}
+
+fn epoch_datetime(value: &DataType) -> Option<NaiveDateTime> {
+    match value {
+        DataType::DateTimeUtc(Some(datetime)) => Some(datetime.naive_utc()),
+        DataType::DateTime(Some(datetime)) => Some(*datetime),
+        DataType::Date(Some(date)) => Some(date.and_hms(0, 0, 0)),
+        _ => None,
+    }
+}
+
+fn iso8601(value: DataType) -> DataType {
+    let text = match value {
+        DataType::DateTimeUtc(Some(datetime)) => {
+            datetime.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+        }
+        DataType::DateTime(Some(datetime)) => datetime.format("%Y-%m-%dT%H:%M:%S%.f").to_string(),
+        DataType::Date(Some(date)) => date.format("%Y-%m-%d").to_string(),
+        DataType::Time(Some(time)) => time.format("%H:%M:%S%.f").to_string(),
+        value => return value,
+    };
+
+    DataType::String(Some(text))
+}
+
+/// Patterns with fields the value does not have fail instead of panicking
+fn format_pattern(formatted: impl std::fmt::Display) -> anyhow::Result<String> {
+    let mut text = String::new();
+    write!(text, "{}", formatted).map_err(|_| anyhow!("date pattern does not fit the value"))?;
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn options(format: &str, source_timezone: Option<&str>) -> DateOptions {
+        DateOptions {
+            format: format.parse().unwrap(),
+            source_timezone: source_timezone.map(|name| parse_timezone(name).unwrap()),
+        }
+    }
+
+    fn text(value: anyhow::Result<DataType>) -> Option<String> {
+        value.unwrap().to_text()
+    }
+
+    #[test]
+    fn test_formats_dates() {
+        let datetime = NaiveDate::from_ymd(2021, 1, 1).and_hms(10, 0, 0);
+        let utc = || DataType::DateTimeUtc(Some(Utc.from_utc_datetime(&datetime)));
+
+        assert_eq!(text(options("iso8601", None).format(utc())).unwrap(), "2021-01-01T10:00:00Z");
+        assert_eq!(
+            text(options("iso8601", None).format(DataType::DateTime(Some(datetime)))).unwrap(),
+            "2021-01-01T10:00:00"
+        );
+        assert_eq!(text(options("epoch_seconds", None).format(utc())).unwrap(), "1609495200");
+        assert_eq!(text(options("epoch_millis", None).format(utc())).unwrap(), "1609495200000");
+        assert_eq!(
+            text(options("%d.%m.%Y", None).format(DataType::Date(Some(datetime.date()))))
+                .unwrap(),
+            "01.01.2021"
+        );
+        assert!(options("%H:%M", None)
+            .format(DataType::Time(Some(datetime.time())))
+            .is_ok());
+        assert!("%Q".parse::<DateFormat>().is_err());
+    }
+
+    #[test]
+    fn test_converts_naive_datetimes_to_utc() {
+        let datetime = NaiveDate::from_ymd(2021, 7, 1).and_hms(12, 0, 0);
+
+        let value = options("iso8601", Some("Europe/Berlin"))
+            .to_utc(DataType::DateTime(Some(datetime)))
+            .unwrap();
+
+        assert_eq!(text(options("iso8601", None).format(value)).unwrap(), "2021-07-01T10:00:00Z");
+    }
+}
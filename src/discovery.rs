@@ -0,0 +1,115 @@
+use anyhow::anyhow;
+use indexmap::IndexMap;
+use regex::Regex;
+
+use crate::config::{Config, TableConfig};
+
+/// Tables matching the include but none of the exclude patterns of the
+/// database config, which are not configured explicitly.
+///
+/// Discovered tables are extracted with all of their columns.
+pub fn discover_tables(
+    config: &Config,
+    table_names: Vec<String>,
+) -> anyhow::Result<Vec<TableConfig>> {
+    let include = patterns(&config.database.include)?;
+    let exclude = patterns(&config.database.exclude)?;
+
+    Ok(table_names
+        .into_iter()
+        .filter(|name| {
+            include.iter().any(|pattern| pattern.is_match(name))
+                && !exclude.iter().any(|pattern| pattern.is_match(name))
+                && !config.tables.iter().any(|table| &table.name == name)
+        })
+        .map(|name| TableConfig {
+            name,
+            columns: vec!["*".to_string()],
+            ..Default::default()
+        })
+        .collect())
+}
+
+/// Replaces `*` in the columns of `table` by all columns of its schema, in
+/// their ordinal order
+pub fn expand_columns(table: &mut TableConfig, schema: &IndexMap<String, String>) {
+    table.columns = table
+        .columns
+        .iter()
+        .flat_map(|column| {
+            if column == "*" {
+                schema.keys().cloned().collect()
+            } else {
+                vec![column.clone()]
+            }
+        })
+        .collect();
+}
+
+/// Patterns are globs supporting `*` and `?`, or regular expressions if they
+/// are enclosed in slashes like `/^order_\d+$/`
+fn patterns(patterns: &[String]) -> anyhow::Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            let regex = if pattern.len() > 1 && pattern.starts_with('/') && pattern.ends_with('/') {
+                pattern[1..pattern.len() - 1].to_string()
+            } else {
+                glob_to_regex(pattern)
+            };
+            Regex::new(&regex).map_err(|err| anyhow!("invalid table pattern {}: {}", pattern, err))
+        })
+        .collect()
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let regex = glob
+        .split('*')
+        .map(|part| {
+            part.split('?')
+                .map(regex::escape)
+                .collect::<Vec<String>>()
+                .join(".")
+        })
+        .collect::<Vec<String>>()
+        .join(".*");
+
+    format!("^{}$", regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        let glob = &patterns(&["order_*".to_string(), "log?".to_string()]).unwrap();
+        assert!(glob[0].is_match("order_items"));
+        assert!(!glob[0].is_match("orders"));
+        assert!(glob[1].is_match("logs"));
+        assert!(!glob[1].is_match("log"));
+
+        let regex = &patterns(&["/^tmp_\\d+$/".to_string()]).unwrap();
+        assert!(regex[0].is_match("tmp_42"));
+        assert!(!regex[0].is_match("tmp_a"));
+
+        assert!(patterns(&["/order_(/".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_expand_columns() {
+        let mut table = TableConfig {
+            name: "orders".to_string(),
+            columns: vec!["*".to_string()],
+            ..Default::default()
+        };
+        let mut schema = IndexMap::new();
+        schema.insert("id".to_string(), "int".to_string());
+        schema.insert("created_at".to_string(), "datetime".to_string());
+        schema.insert("amount".to_string(), "decimal".to_string());
+
+        expand_columns(&mut table, &schema);
+
+        assert_eq!(vec!["id", "created_at", "amount"], table.columns);
+    }
+}
@@ -0,0 +1,70 @@
+use anyhow::bail;
+use std::collections::HashMap;
+
+/// File names of the tables unless a template is given
+pub const DEFAULT_TEMPLATE: &str = "{table}.{ext}";
+
+const PLACEHOLDERS: [&str; 6] = ["table", "schema", "database", "date", "run_id", "ext"];
+
+/// Replaces the placeholders `{name}` in a file name template. The name may
+/// contain directories, but has to stay inside the output directory.
+pub fn render(template: &str, values: &HashMap<&str, &str>) -> anyhow::Result<String> {
+    let mut name = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => bail!("unclosed placeholder in file name template {}", template),
+        };
+        let placeholder = &rest[start + 1..end];
+        match values.get(placeholder) {
+            Some(value) => name.push_str(value),
+            None => bail!(
+                "unknown placeholder {{{}}} in file name template {}, use one of {}",
+                placeholder,
+                template,
+                PLACEHOLDERS.join(", ")
+            ),
+        }
+        rest = &rest[end + 1..];
+    }
+    name.push_str(rest);
+
+    if name.is_empty()
+        || name.starts_with('/')
+        || name.ends_with('/')
+        || name.split('/').any(|part| part.is_empty() || part == "." || part == "..")
+    {
+        bail!("file name {} of template {} is not a relative path", name, template);
+    }
+
+    Ok(name)
+}
+
+/// Fails early on templates of the command line or the config
+pub fn validate_template(template: &str) -> anyhow::Result<()> {
+    let values = PLACEHOLDERS.iter().map(|placeholder| (*placeholder, "x")).collect();
+    render(template, &values)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_renders_templates() {
+        let values: HashMap<&str, &str> = vec![("table", "orders"), ("date", "2021-01-01")]
+            .into_iter()
+            .collect();
+
+        assert_eq!(render("{date}/{table}.json", &values).unwrap(), "2021-01-01/orders.json");
+        assert!(render("{table}_{user}.json", &values).is_err());
+        assert!(render("{table.json", &values).is_err());
+        assert!(render("../{table}.json", &values).is_err());
+        assert!(validate_template(DEFAULT_TEMPLATE).is_ok());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::config::TableConfig;
+
+/// A foreign key between two tables, with the columns in matching order
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+    pub name: String,
+    pub table: String,
+    pub columns: Vec<String>,
+    pub referenced_table: String,
+    pub referenced_columns: Vec<String>,
+}
+
+/// One row of a foreign key lookup in `INFORMATION_SCHEMA`
+pub struct ForeignKeyColumn {
+    pub constraint_name: String,
+    pub table: String,
+    pub column: String,
+    pub referenced_table: String,
+    pub referenced_column: String,
+}
+
+/// Groups the column rows of `INFORMATION_SCHEMA.KEY_COLUMN_USAGE` into
+/// foreign keys. Rows have to be ordered by constraint and ordinal position.
+pub fn group_foreign_keys(columns: Vec<ForeignKeyColumn>) -> Vec<ForeignKey> {
+    let mut foreign_keys: Vec<ForeignKey> = Vec::new();
+
+    for column in columns {
+        match foreign_keys.last_mut() {
+            Some(fk) if fk.name == column.constraint_name && fk.table == column.table => {
+                fk.columns.push(column.column);
+                fk.referenced_columns.push(column.referenced_column);
+            }
+            _ => foreign_keys.push(ForeignKey {
+                name: column.constraint_name,
+                table: column.table,
+                columns: vec![column.column],
+                referenced_table: column.referenced_table,
+                referenced_columns: vec![column.referenced_column],
+            }),
+        }
+    }
+
+    foreign_keys
+}
+
+/// Orders the tables so that every table comes after the tables it references.
+///
+/// Tables without dependencies between them keep their configured order.
+/// Self references are ignored and tables taking part in a cycle are appended
+/// in configured order at the end.
+pub fn dependency_order<'a>(
+    tables: &'a [TableConfig],
+    foreign_keys: &[ForeignKey],
+) -> Vec<&'a TableConfig> {
+    let mut remaining: Vec<&TableConfig> = tables.iter().collect();
+    let mut ordered = Vec::with_capacity(tables.len());
+
+    while !remaining.is_empty() {
+        let next = remaining.iter().position(|table| {
+            !foreign_keys.iter().any(|fk| {
+                fk.table == table.name
+                    && fk.referenced_table != table.name
+                    && remaining
+                        .iter()
+                        .any(|other| other.name == fk.referenced_table)
+            })
+        });
+
+        match next {
+            Some(idx) => ordered.push(remaining.remove(idx)),
+            None => ordered.append(&mut remaining),
+        }
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(name: &str) -> TableConfig {
+        TableConfig {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn fk(table: &str, referenced_table: &str) -> ForeignKey {
+        ForeignKey {
+            name: format!("fk_{}_{}", table, referenced_table),
+            table: table.to_string(),
+            columns: vec![format!("{}_id", referenced_table)],
+            referenced_table: referenced_table.to_string(),
+            referenced_columns: vec!["id".to_string()],
+        }
+    }
+
+    fn names(tables: Vec<&TableConfig>) -> Vec<&str> {
+        tables.iter().map(|table| table.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_dependency_order() {
+        let tables = vec![
+            table("order_items"),
+            table("orders"),
+            table("logs"),
+            table("customers"),
+        ];
+        let foreign_keys = vec![
+            fk("order_items", "orders"),
+            fk("orders", "customers"),
+            fk("customers", "customers"),
+        ];
+
+        assert_eq!(
+            vec!["logs", "customers", "orders", "order_items"],
+            names(dependency_order(&tables, &foreign_keys))
+        );
+    }
+
+    #[test]
+    fn test_dependency_order_with_cycle() {
+        let tables = vec![table("a"), table("b"), table("c")];
+        let foreign_keys = vec![fk("a", "b"), fk("b", "a")];
+
+        assert_eq!(
+            vec!["c", "a", "b"],
+            names(dependency_order(&tables, &foreign_keys))
+        );
+    }
+
+    #[test]
+    fn test_group_foreign_keys() {
+        let column = |name: &str, column: &str| ForeignKeyColumn {
+            constraint_name: name.to_string(),
+            table: "order_items".to_string(),
+            column: column.to_string(),
+            referenced_table: "orders".to_string(),
+            referenced_column: column.to_string(),
+        };
+
+        let foreign_keys = group_foreign_keys(vec![
+            column("fk_a", "order_id"),
+            column("fk_a", "shop_id"),
+            column("fk_b", "product_id"),
+        ]);
+
+        assert_eq!(2, foreign_keys.len());
+        assert_eq!(vec!["order_id", "shop_id"], foreign_keys[0].columns);
+        assert_eq!(vec!["product_id"], foreign_keys[1].referenced_columns);
+    }
+}
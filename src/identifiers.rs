@@ -0,0 +1,108 @@
+use anyhow::bail;
+use indexmap::IndexMap;
+
+use crate::config::TableConfig;
+
+/// Quotes an identifier for MySQL, so reserved words, spaces and mixed case
+/// can be used and nothing can be injected through it
+pub fn mysql_identifier(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
+
+/// Quotes an identifier for SQL Server, see [`mysql_identifier`]
+pub fn mssql_identifier(name: &str) -> String {
+    format!("[{}]", name.replace(']', "]]"))
+}
+
+/// Comma separated list of quoted column names
+pub fn column_list(columns: &[String], quote: fn(&str) -> String) -> String {
+    columns
+        .iter()
+        .map(|column| quote(column))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Checks that the table exists and has all configured columns. Names are
+/// compared case insensitively, like both databases do by default.
+pub fn validate_table(
+    table: &TableConfig,
+    schema: &IndexMap<String, String>,
+) -> anyhow::Result<()> {
+    if schema.is_empty() {
+        bail!("table {} does not exist", table.name);
+    }
+
+    for column in &table.columns {
+        if column != "*" && !schema.keys().any(|name| name.eq_ignore_ascii_case(column)) {
+            bail!("table {} has no column {}", table.name, column);
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the output has no column twice, like `a.id, b.id` of a join,
+/// which would collapse into one key of the json objects
+pub fn validate_unique_columns(table: &TableConfig, columns: &[String]) -> anyhow::Result<()> {
+    for (idx, column) in columns.iter().enumerate() {
+        if columns[..idx].contains(column) {
+            bail!(
+                "table {} has the column {} more than once, give the columns distinct aliases",
+                table.name,
+                column
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_identifiers() {
+        assert_eq!("`order`", mysql_identifier("order"));
+        assert_eq!("`a``b`", mysql_identifier("a`b"));
+        assert_eq!("[first name]", mssql_identifier("first name"));
+        assert_eq!("[a]]; drop table x]", mssql_identifier("a]; drop table x"));
+        assert_eq!(
+            "`id`,`Name`",
+            column_list(&["id".to_string(), "Name".to_string()], mysql_identifier)
+        );
+    }
+
+    #[test]
+    fn test_validate_table() {
+        let mut schema = IndexMap::new();
+        schema.insert("Id".to_string(), "int".to_string());
+        schema.insert("name".to_string(), "varchar".to_string());
+
+        let mut table = TableConfig {
+            name: "customers".to_string(),
+            columns: vec!["id".to_string(), "*".to_string()],
+            ..Default::default()
+        };
+        assert!(validate_table(&table, &schema).is_ok());
+
+        table.columns.push("email".to_string());
+        assert!(validate_table(&table, &schema).is_err());
+        assert!(validate_table(&table, &IndexMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_validate_unique_columns() {
+        let table = TableConfig {
+            name: "report".to_string(),
+            ..Default::default()
+        };
+        let columns = |names: &[&str]| -> Vec<String> {
+            names.iter().map(|name| name.to_string()).collect()
+        };
+
+        assert!(validate_unique_columns(&table, &columns(&["id", "name"])).is_ok());
+        assert!(validate_unique_columns(&table, &columns(&["id", "name", "id"])).is_err());
+    }
+}
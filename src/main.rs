@@ -1,11 +1,31 @@
+pub mod binary_files;
 pub mod config;
 pub mod data_types;
+pub mod dates;
+pub mod discovery;
+pub mod file_names;
+pub mod foreign_keys;
+pub mod identifiers;
+pub mod manifest;
+pub mod masking;
+pub mod output;
+pub mod params;
+pub mod partitions;
+pub mod pipeline;
+pub mod pseudonymization;
+pub mod s3;
 pub mod writer;
+pub mod spatial;
 pub mod sql;
+pub mod subset;
 
 use anyhow::bail;
 use config::{Config, DatabaseType};
-use writer::DatabaseWriter;
+use data_types::BinaryEncoding;
+use dates::{DateFormat, DateOptions};
+use output::{Compression, Pipe};
+use s3::S3Output;
+use writer::{DatabaseWriter, ExistingFiles, JsonLayout, WriterOptions};
 use writer::mssql_writer::MssqlWriter;
 use writer::mysql_writer::MySqlWriter;
 use tiberius::AuthMethod;
@@ -13,6 +33,7 @@ use tiberius::AuthMethod;
 use std::fs::read_to_string;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 use structopt::StructOpt;
 
 #[tokio::main]
@@ -24,19 +45,67 @@ async fn main() -> anyhow::Result<()> {
 
     let config: Config = serde_json::from_str(&contents).unwrap();
 
+    if let (Some(compression), Some(level)) = (opt.compress, opt.compression_level) {
+        compression.validate_level(level)?;
+    }
+
+    file_names::validate_template(&opt.file_name)?;
+    validate_config(&config)?;
+
+    let s3 = S3Output::from_output(&opt.output.to_string_lossy(), opt.s3_endpoint)?;
+    let pipe = Pipe::from_output(&opt.output);
+    if pipe.is_some() && (opt.max_rows_per_file.is_some() || opt.max_bytes_per_file.is_some()) {
+        bail!("output written to a pipe can not be split into files");
+    }
+
+    // failing is the default, --fail-if-exists only makes it explicit in scripts
+    let existing_files = match (opt.overwrite, opt.skip_existing) {
+        (true, _) => ExistingFiles::Overwrite,
+        (false, true) => ExistingFiles::Skip,
+        (false, false) => ExistingFiles::Fail,
+    };
+
+    let mut params = params::builtin_params();
+    params.extend(opt.params);
+
+    let options = WriterOptions {
+        dir: opt.output,
+        params,
+        order_by_foreign_keys: opt.order_by_foreign_keys,
+        disable_constraint_checks: opt.disable_constraint_checks,
+        json_binary_encoding: opt.json_binary_encoding,
+        sql_binary_encoding: opt.sql_binary_encoding,
+        dates: DateOptions {
+            format: opt.date_format,
+            source_timezone: opt.source_timezone,
+        },
+        json_layout: opt.json_layout,
+        compression: opt.compress,
+        compression_level: opt.compression_level,
+        max_rows_per_file: opt.max_rows_per_file,
+        max_bytes_per_file: opt.max_bytes_per_file,
+        pipe,
+        s3: s3.map(Arc::new),
+        existing_files,
+        file_name_template: opt.file_name,
+        verify: opt.verify,
+    };
+
     match config.database.database_type {
         config::DatabaseType::MsSQL => {
-            let mssql_writer = MssqlWriter::new(&config, opt.output).await?;
+            let mssql_writer = MssqlWriter::new(&config, options).await?;
             match opt._type {
                 OutputType::Json => mssql_writer.database_to_json().await?,
-                OutputType::Sql => mssql_writer.database_to_sql().await?
+                OutputType::Sql => mssql_writer.database_to_sql().await?,
+                OutputType::GeoJson => mssql_writer.database_to_geojson().await?
             }
         },
         config::DatabaseType::MySQL => {
-            let mysql_writer = MySqlWriter::new(&config, opt.output).await?;
+            let mysql_writer = MySqlWriter::new(&config, options).await?;
             match opt._type {
                 OutputType::Json => mysql_writer.database_to_json().await?,
-                OutputType::Sql => mysql_writer.database_to_sql().await?
+                OutputType::Sql => mysql_writer.database_to_sql().await?,
+                OutputType::GeoJson => mysql_writer.database_to_geojson().await?
             }
         },
     }
@@ -47,19 +116,108 @@ async fn main() -> anyhow::Result<()> {
 #[derive(Debug, StructOpt)]
 #[structopt(name = "example", about = "An example of StructOpt usage.")]
 struct Opt {
-    /// Output directory
+    /// Output directory, s3://bucket/prefix to upload the files, or `-` for
+    /// stdout or a named pipe to stream the output of a single table
     #[structopt(parse(from_os_str), default_value = "/tmp", short, long)]
     output: PathBuf,
 
-    /// Output type (json or sql)
+    /// Output type (json, sql or geojson)
     #[structopt(default_value = "json", long)]
     _type: OutputType,
+
+    /// Write sql files in foreign key dependency order, prefixed with their position
+    #[structopt(long)]
+    order_by_foreign_keys: bool,
+
+    /// Disable constraint checks while the sql files are loaded
+    #[structopt(long)]
+    disable_constraint_checks: bool,
+
+    /// Value of a named parameter used as :name in where clauses and queries,
+    /// given as name=value. run_date, run_timestamp and run_id are always
+    /// available.
+    #[structopt(long = "param", parse(try_from_str = params::parse_param))]
+    params: Vec<(String, String)>,
+
+    /// Encoding of binary values in json output (base64, hex or literal)
+    #[structopt(long, default_value = "base64")]
+    json_binary_encoding: BinaryEncoding,
+
+    /// Encoding of binary values in sql output (literal, hex or base64)
+    #[structopt(long, default_value = "literal")]
+    sql_binary_encoding: BinaryEncoding,
+
+    /// Format of date and time values (text, iso8601, epoch_seconds,
+    /// epoch_millis or a strftime pattern like %d.%m.%Y)
+    #[structopt(long, default_value = "text")]
+    date_format: DateFormat,
+
+    /// Time zone of datetimes without one like Europe/Berlin, which are
+    /// converted to UTC
+    #[structopt(long, parse(try_from_str = dates::parse_timezone))]
+    source_timezone: Option<chrono_tz::Tz>,
+
+    /// Layout of json files, objects keyed by column name or compact with
+    /// the column names once and the rows as arrays
+    #[structopt(long, default_value = "objects")]
+    json_layout: JsonLayout,
+
+    /// Compress the output files (gzip, zstd or bzip2), appending the extension
+    #[structopt(long)]
+    compress: Option<Compression>,
+
+    /// Level of the compression, 1-9 for gzip and bzip2, 1-22 for zstd
+    #[structopt(long, requires = "compress")]
+    compression_level: Option<u32>,
+
+    /// Split the output of a table into numbered part files of at most this
+    /// many rows
+    #[structopt(long)]
+    max_rows_per_file: Option<usize>,
+
+    /// Split the output of a table into numbered part files of at most this
+    /// many bytes before compression
+    #[structopt(long)]
+    max_bytes_per_file: Option<usize>,
+
+    /// Endpoint of S3 compatible storage like http://localhost:9000 for MinIO,
+    /// AWS_ENDPOINT_URL if not set. Credentials are taken from AWS_ACCESS_KEY_ID,
+    /// AWS_SECRET_ACCESS_KEY and AWS_SESSION_TOKEN.
+    #[structopt(long)]
+    s3_endpoint: Option<String>,
+
+    /// Replace the output of tables which exists from a previous run, and
+    /// remove its part files and partitions which were not written again
+    #[structopt(long, conflicts_with_all = &["skip-existing", "fail-if-exists"])]
+    overwrite: bool,
+
+    /// Leave out tables whose output exists from a previous run
+    #[structopt(long, conflicts_with = "fail-if-exists")]
+    skip_existing: bool,
+
+    /// Stop before writing anything if the output of a table exists from a
+    /// previous run, which is also done without any of these flags
+    #[structopt(long)]
+    _fail_if_exists: bool,
+
+    /// Template of the output file names, with the placeholders {table},
+    /// {schema}, {database}, {date}, {run_id} and {ext}. Tables can set their
+    /// own in output_name. The manifest is named by it with the table manifest.
+    #[structopt(long, default_value = file_names::DEFAULT_TEMPLATE)]
+    file_name: String,
+
+    /// Count the rows of every table in the database with the same filter or
+    /// query and fail if a different number was written. Without snapshot in
+    /// the config they are counted again afterwards and must not change.
+    #[structopt(long)]
+    verify: bool,
 }
 
 #[derive(Debug)]
 enum OutputType {
     Json,
     Sql,
+    GeoJson,
 }
 
 impl FromStr for OutputType {
@@ -70,10 +228,29 @@ impl FromStr for OutputType {
             Ok(Self::Json)
         } else if s.eq("sql") {
             Ok(Self::Sql)
+        } else if s.eq("geojson") {
+            Ok(Self::GeoJson)
         } else {
-            bail!("output type can only be json or sql")
+            bail!("output type can only be json, sql or geojson")
+        }
+    }
+}
+
+/// Checks the settings of the tables which do not need the database
+fn validate_config(config: &Config) -> anyhow::Result<()> {
+    for table in &config.tables {
+        if table.query.is_some() && table.where_clause.is_some() {
+            bail!(
+                "table {} has a query and a where clause, filter the rows in the query instead",
+                table.name
+            );
+        }
+        if let Some(output_name) = &table.output_name {
+            file_names::validate_template(output_name)?;
         }
     }
+
+    Ok(())
 }
 
 // TODO move this somwhere else
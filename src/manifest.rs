@@ -0,0 +1,216 @@
+use anyhow::bail;
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::data_types::BinaryFile;
+
+/// Stands for the table in the file name template to name the manifest, so
+/// it is `manifest.json` with the default template
+pub const MANIFEST_NAME: &str = "manifest";
+
+/// Summary of a run written next to its files, so they can be checked for
+/// completeness before they are loaded. It is written last, only if the run
+/// succeeds.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    version: String,
+    started_at: String,
+    finished_at: Option<String>,
+    tables: Vec<ManifestTable>,
+    /// Start of the table which is extracted now
+    #[serde(skip)]
+    table_started_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ManifestTable {
+    name: String,
+    query: String,
+    /// Start and end of the extraction of the table, missing in manifests
+    /// of older versions
+    #[serde(default)]
+    started_at: String,
+    #[serde(default)]
+    finished_at: String,
+    columns: Vec<ManifestColumn>,
+    rows: usize,
+    /// Rows counted in the database with `--verify`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_rows: Option<usize>,
+    files: Vec<ManifestFile>,
+    /// Files of the binary values with `binary_files`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    binary_files: Vec<BinaryFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ManifestColumn {
+    name: String,
+    /// Type of the column in the result set of the query
+    #[serde(rename = "type")]
+    data_type: String,
+}
+
+/// File as it is stored, after compression
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ManifestFile {
+    /// Path relative to the output directory or prefix
+    pub path: String,
+    pub rows: usize,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+impl Manifest {
+    pub fn start() -> Manifest {
+        Manifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: now(),
+            finished_at: None,
+            tables: Vec::new(),
+            table_started_at: None,
+        }
+    }
+
+    /// Starts a run which keeps the tables of an earlier one, for tables
+    /// which are skipped because their output exists
+    pub fn continue_from(earlier: Manifest) -> Manifest {
+        Manifest {
+            tables: earlier.tables,
+            ..Manifest::start()
+        }
+    }
+
+    /// Marks the start of the extraction of the table added next
+    pub fn start_table(&mut self) {
+        self.table_started_at = Some(now());
+    }
+
+    /// Adds a table once its files are written, replacing one of the same
+    /// name from an earlier run
+    pub fn add_table(
+        &mut self,
+        name: &str,
+        query: &str,
+        columns: &[String],
+        types: &[String],
+        source_rows: Option<usize>,
+        files: Vec<ManifestFile>,
+    ) {
+        let finished_at = now();
+        self.tables.retain(|table| table.name != name);
+        self.tables.push(ManifestTable {
+            name: name.to_string(),
+            query: query.to_string(),
+            started_at: self.table_started_at.take().unwrap_or_else(|| finished_at.clone()),
+            finished_at,
+            columns: columns
+                .iter()
+                .zip(types)
+                .map(|(name, data_type)| ManifestColumn {
+                    name: name.clone(),
+                    data_type: data_type.clone(),
+                })
+                .collect(),
+            rows: files.iter().map(|file| file.rows).sum(),
+            source_rows,
+            files,
+            binary_files: Vec::new(),
+        });
+    }
+
+    pub fn add_binary_files(&mut self, name: &str, binary_files: Vec<BinaryFile>) {
+        if let Some(table) = self.tables.iter_mut().find(|table| table.name == name) {
+            table.binary_files = binary_files;
+        }
+    }
+
+    /// Fails if a table has a different number of rows in the database than
+    /// written
+    pub fn verify(&self) -> anyhow::Result<()> {
+        let mismatches: Vec<String> = self
+            .tables
+            .iter()
+            .filter_map(|table| match table.source_rows {
+                Some(source_rows) if source_rows != table.rows => Some(format!(
+                    "{} has {} rows in the database, but {} were written",
+                    table.name, source_rows, table.rows
+                )),
+                _ => None,
+            })
+            .collect();
+        if !mismatches.is_empty() {
+            bail!("row counts do not match: {}", mismatches.join("; "));
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Manifest {
+        self.finished_at = Some(now());
+        self
+    }
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_rows_of_all_files() {
+        let file = |path: &str, rows| ManifestFile {
+            path: path.to_string(),
+            rows,
+            bytes: 10,
+            sha256: String::new(),
+        };
+        let mut manifest = Manifest::start();
+        manifest.start_table();
+        manifest.add_table(
+            "orders",
+            "select id from orders",
+            &["id".to_string()],
+            &["INT".to_string()],
+            Some(3),
+            vec![file("orders.part-0001.json", 2), file("orders.part-0002.json", 1)],
+        );
+        assert!(manifest.verify().is_ok());
+        let table = &manifest.tables[0];
+        assert!(manifest.started_at <= table.started_at && table.started_at <= table.finished_at);
+
+        let manifest = serde_json::to_value(manifest.finish()).unwrap();
+        assert_eq!(manifest["tables"][0]["rows"], 3);
+        assert_eq!(manifest["tables"][0]["source_rows"], 3);
+        assert_eq!(manifest["tables"][0]["columns"][0]["type"], "INT");
+        assert!(manifest["tables"][0]["started_at"].is_string());
+        assert!(manifest["finished_at"].is_string());
+    }
+
+    #[test]
+    fn test_continues_with_the_tables_of_an_earlier_run() {
+        let mut earlier = Manifest::start();
+        earlier.add_table("orders", "select id from orders", &[], &[], None, Vec::new());
+        earlier.add_table("customers", "select id from customers", &[], &[], None, Vec::new());
+        let earlier: Manifest =
+            serde_json::from_str(&serde_json::to_string(&earlier.finish()).unwrap()).unwrap();
+
+        let mut manifest = Manifest::continue_from(earlier);
+        manifest.add_table("orders", "select id from orders", &[], &[], Some(0), Vec::new());
+
+        let names: Vec<&str> = manifest.tables.iter().map(|table| table.name.as_str()).collect();
+        assert_eq!(names, vec!["customers", "orders"]);
+        assert!(manifest.finished_at.is_none());
+    }
+
+    #[test]
+    fn test_fails_on_missing_rows() {
+        let mut manifest = Manifest::start();
+        manifest.add_table("orders", "select id from orders", &[], &[], Some(3), Vec::new());
+
+        assert!(manifest.verify().is_err());
+    }
+}
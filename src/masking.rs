@@ -0,0 +1,231 @@
+use chrono::Duration;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+
+use crate::config::{MaskingRule, TableConfig};
+use crate::data_types::DataType;
+
+const FIRST_NAMES: &[&str] = &[
+    "Alex", "Anna", "Ben", "Clara", "David", "Emma", "Felix", "Hannah", "Jonas", "Julia", "Lena",
+    "Lukas", "Maria", "Noah", "Paul", "Sarah",
+];
+const LAST_NAMES: &[&str] = &[
+    "Becker",
+    "Fischer",
+    "Hoffmann",
+    "Klein",
+    "Koch",
+    "Meyer",
+    "Miller",
+    "Richter",
+    "Schmidt",
+    "Schneider",
+    "Schulz",
+    "Smith",
+    "Wagner",
+    "Weber",
+    "Wolf",
+    "Young",
+];
+
+/// Applies the masking rule configured for `column`, if any
+pub fn mask_column(table: &TableConfig, column: &str, value: DataType) -> DataType {
+    match table.masking.get(column) {
+        Some(rule) => mask(rule, value),
+        None => value,
+    }
+}
+
+/// Masks a single value. Nulls are kept as they are.
+pub fn mask(rule: &MaskingRule, value: DataType) -> DataType {
+    let text = match value.to_text() {
+        Some(text) => text,
+        None => return value,
+    };
+
+    match rule {
+        MaskingRule::Hash { salt } => {
+            let mut hasher = Sha256::new();
+            hasher.update(salt.as_bytes());
+            hasher.update(text.as_bytes());
+            DataType::String(Some(hex::encode(hasher.finalize())))
+        }
+        MaskingRule::Redact { replacement } => DataType::String(Some(
+            replacement
+                .clone()
+                .unwrap_or_else(|| "REDACTED".to_string()),
+        )),
+        MaskingRule::Nullify => value.to_null(),
+        MaskingRule::Truncate { length } => match value {
+            DataType::String(_) => DataType::String(Some(text.chars().take(*length).collect())),
+            value => value,
+        },
+        MaskingRule::FakeEmail { salt } => DataType::String(Some(fake_email(salt, &text))),
+        MaskingRule::FakeName { salt } => DataType::String(Some(fake_name(salt, &text))),
+        MaskingRule::FakePhone { salt } => DataType::String(Some(scramble(salt, &text, &text))),
+        MaskingRule::DateShift { days } => shift_date(value, *days),
+    }
+}
+
+fn fake_email(salt: &str, email: &str) -> String {
+    let local = email.split('@').next().unwrap_or_default();
+    format!("{}@example.com", scramble(salt, local, email))
+}
+
+fn fake_name(salt: &str, name: &str) -> String {
+    let words = name.split_whitespace().count().max(1);
+    let bytes = pseudo_random_bytes(salt, name, words);
+
+    bytes
+        .iter()
+        .enumerate()
+        .map(|(idx, byte)| {
+            let names = if idx == 0 { FIRST_NAMES } else { LAST_NAMES };
+            names[*byte as usize % names.len()]
+        })
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Replaces letters and digits with pseudo random ones of the same kind,
+/// keeping everything else (separators, `+`, `.`) in place
+fn scramble(salt: &str, text: &str, seed: &str) -> String {
+    let bytes = pseudo_random_bytes(salt, seed, text.chars().count());
+
+    text.chars()
+        .zip(bytes)
+        .map(|(c, byte)| {
+            if c.is_ascii_lowercase() {
+                (b'a' + byte % 26) as char
+            } else if c.is_ascii_uppercase() {
+                (b'A' + byte % 26) as char
+            } else if c.is_ascii_digit() {
+                (b'0' + byte % 10) as char
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// Deterministic bytes derived from `seed`, keyed with `salt` so they can
+/// not be computed for guessed originals without it
+fn pseudo_random_bytes(salt: &str, seed: &str, len: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(len);
+    let mut counter: u32 = 0;
+    while bytes.len() < len {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(salt.as_bytes()).expect("HMAC accepts any key length");
+        mac.update(&counter.to_be_bytes());
+        mac.update(seed.as_bytes());
+        bytes.extend_from_slice(&mac.finalize().into_bytes());
+        counter += 1;
+    }
+    bytes.truncate(len);
+
+    bytes
+}
+
+fn shift_date(value: DataType, days: i64) -> DataType {
+    let shift = Duration::days(days);
+    match value {
+        DataType::Date(date) => DataType::Date(date.map(|date| date + shift)),
+        DataType::DateTime(datetime) => {
+            DataType::DateTime(datetime.map(|datetime| datetime + shift))
+        }
+        DataType::DateTimeUtc(datetime) => {
+            DataType::DateTimeUtc(datetime.map(|datetime| datetime + shift))
+        }
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(data: DataType) -> Option<String> {
+        data.to_text()
+    }
+
+    fn fake_email_rule(salt: &str) -> MaskingRule {
+        MaskingRule::FakeEmail {
+            salt: salt.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_hash_is_salted_and_deterministic() {
+        let rule = MaskingRule::Hash {
+            salt: "a".to_string(),
+        };
+        let other = MaskingRule::Hash {
+            salt: "b".to_string(),
+        };
+
+        assert_eq!(
+            text(mask(&rule, DataType::Int(Some(42)))),
+            text(mask(&rule, DataType::Int(Some(42))))
+        );
+        assert_ne!(
+            text(mask(&rule, DataType::Int(Some(42)))),
+            text(mask(&other, DataType::Int(Some(42))))
+        );
+    }
+
+    #[test]
+    fn test_nulls_are_kept() {
+        assert_eq!(
+            None,
+            text(mask(&fake_email_rule("a"), DataType::String(None)))
+        );
+        assert_eq!(
+            None,
+            text(mask(&MaskingRule::Nullify, DataType::Int(Some(1))))
+        );
+    }
+
+    #[test]
+    fn test_fake_values_keep_format() {
+        let phone = text(mask(
+            &MaskingRule::FakePhone {
+                salt: "a".to_string(),
+            },
+            DataType::String(Some("+49 (30) 123-456".to_string())),
+        ))
+        .unwrap();
+        assert_eq!(16, phone.len());
+        assert!(phone.starts_with("+"));
+        assert_eq!(" (", &phone[3..5]);
+
+        let email = |salt| {
+            text(mask(
+                &fake_email_rule(salt),
+                DataType::String(Some("john.doe@company.org".to_string())),
+            ))
+            .unwrap()
+        };
+        assert!(email("a").ends_with("@example.com"));
+        assert_eq!(Some(4), email("a").find('.'));
+        assert_eq!(email("a"), email("a"));
+        assert_ne!(email("a"), email("b"));
+    }
+
+    #[test]
+    fn test_truncate_and_date_shift() {
+        assert_eq!(
+            Some("abc".to_string()),
+            text(mask(
+                &MaskingRule::Truncate { length: 3 },
+                DataType::String(Some("abcdef".to_string()))
+            ))
+        );
+        assert_eq!(
+            Some("2021-01-03".to_string()),
+            text(mask(
+                &MaskingRule::DateShift { days: 2 },
+                DataType::Date(Some(chrono::NaiveDate::from_ymd(2021, 1, 1)))
+            ))
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use anyhow::bail;
+use sha2::{Digest, Sha256};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::s3::S3Upload;
+
+/// Destination of the output instead of files in a directory
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pipe {
+    Stdout,
+    /// Named pipe created with `mkfifo`
+    Fifo(PathBuf),
+}
+
+impl Pipe {
+    /// `-` stands for stdout, like with most tools
+    pub fn from_output(output: &Path) -> Option<Pipe> {
+        if output == Path::new("-") {
+            Some(Pipe::Stdout)
+        } else if output.metadata().is_ok_and(|metadata| metadata.file_type().is_fifo()) {
+            Some(Pipe::Fifo(output.to_path_buf()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Where the bytes of an output file end up
+pub enum Sink {
+    File(File),
+    Atomic(AtomicFile),
+    Stdout(io::Stdout),
+    S3(S3Upload),
+}
+
+impl Sink {
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Sink::File(mut file) => Ok(file.flush()?),
+            Sink::Atomic(file) => Ok(file.finish()?),
+            Sink::Stdout(mut stdout) => Ok(stdout.flush()?),
+            Sink::S3(upload) => upload.finish(),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::File(file) => file.write(buf),
+            Sink::Atomic(atomic) => atomic.file.write(buf),
+            Sink::Stdout(stdout) => stdout.write(buf),
+            Sink::S3(upload) => upload.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.flush(),
+            Sink::Atomic(atomic) => atomic.file.flush(),
+            Sink::Stdout(stdout) => stdout.flush(),
+            Sink::S3(upload) => upload.flush(),
+        }
+    }
+}
+
+/// File written under a temporary name next to its path and renamed when it
+/// is finished, so a crash does not leave a truncated file that looks complete
+pub struct AtomicFile {
+    file: File,
+    temp_path: PathBuf,
+    path: PathBuf,
+    finished: bool,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> io::Result<AtomicFile> {
+        let mut temp_name = OsString::from(".");
+        temp_name.push(path.file_name().unwrap_or_default());
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        Ok(AtomicFile {
+            file: File::create(&temp_path)?,
+            temp_path,
+            path: path.to_path_buf(),
+            finished: false,
+        })
+    }
+
+    /// Replaces an existing file at the path
+    fn finish(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.temp_path, &self.path)?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            "bzip2" => Ok(Self::Bzip2),
+            _ => bail!("compression can only be gzip, zstd or bzip2"),
+        }
+    }
+}
+
+impl Compression {
+    /// Appended to the file name
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Gzip => "gz",
+            Self::Zstd => "zst",
+            Self::Bzip2 => "bz2",
+        }
+    }
+
+    /// Level used unless one is given, the default of each tool
+    fn default_level(self) -> u32 {
+        match self {
+            Self::Gzip => 6,
+            Self::Zstd => 3,
+            Self::Bzip2 => 9,
+        }
+    }
+
+    pub fn validate_level(self, level: u32) -> anyhow::Result<()> {
+        let max = match self {
+            Self::Gzip | Self::Bzip2 => 9,
+            Self::Zstd => 22,
+        };
+        if level < 1 || level > max {
+            bail!("{:?} compression level must be between 1 and {}", self, max);
+        }
+        Ok(())
+    }
+}
+
+/// Size and SHA-256 of the bytes of a file as stored, after compression
+#[derive(Debug, PartialEq)]
+pub struct FileChecksum {
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// Sink counting and hashing the bytes written to it
+pub struct ChecksumSink {
+    sink: Sink,
+    bytes: u64,
+    sha256: Sha256,
+}
+
+impl ChecksumSink {
+    fn finish(self) -> anyhow::Result<FileChecksum> {
+        self.sink.finish()?;
+        Ok(FileChecksum {
+            bytes: self.bytes,
+            sha256: hex::encode(self.sha256.finalize()),
+        })
+    }
+}
+
+impl Write for ChecksumSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.sink.write(buf)?;
+        self.sha256.update(&buf[..written]);
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+/// Output file, compressed while it is written
+pub enum OutputFile {
+    Plain(BufWriter<ChecksumSink>),
+    Gzip(flate2::write::GzEncoder<BufWriter<ChecksumSink>>),
+    Zstd(zstd::Encoder<'static, BufWriter<ChecksumSink>>),
+    Bzip2(bzip2::write::BzEncoder<BufWriter<ChecksumSink>>),
+}
+
+impl OutputFile {
+    pub fn new(
+        sink: Sink,
+        compression: Option<Compression>,
+        level: Option<u32>,
+    ) -> io::Result<OutputFile> {
+        let file = BufWriter::new(ChecksumSink {
+            sink,
+            bytes: 0,
+            sha256: Sha256::new(),
+        });
+        let compression = match compression {
+            Some(compression) => compression,
+            None => return Ok(OutputFile::Plain(file)),
+        };
+        let level = level.unwrap_or_else(|| compression.default_level());
+
+        Ok(match compression {
+            Compression::Gzip => OutputFile::Gzip(flate2::write::GzEncoder::new(
+                file,
+                flate2::Compression::new(level),
+            )),
+            Compression::Zstd => OutputFile::Zstd(zstd::Encoder::new(file, level as i32)?),
+            Compression::Bzip2 => OutputFile::Bzip2(bzip2::write::BzEncoder::new(
+                file,
+                bzip2::Compression::new(level),
+            )),
+        })
+    }
+
+    /// Writes the end of the compressed stream and flushes the file. Dropping
+    /// the file without it loses errors, or with zstd the end of the data, and
+    /// uploads are only completed by it.
+    pub fn finish(self) -> anyhow::Result<FileChecksum> {
+        let file = match self {
+            OutputFile::Plain(file) => file,
+            OutputFile::Gzip(encoder) => encoder.finish()?,
+            OutputFile::Zstd(encoder) => encoder.finish()?,
+            OutputFile::Bzip2(encoder) => encoder.finish()?,
+        };
+        file.into_inner().map_err(|err| err.into_error())?.finish()
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputFile::Plain(file) => file.write(buf),
+            OutputFile::Gzip(encoder) => encoder.write(buf),
+            OutputFile::Zstd(encoder) => encoder.write(buf),
+            OutputFile::Bzip2(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(file) => file.flush(),
+            OutputFile::Gzip(encoder) => encoder.flush(),
+            OutputFile::Zstd(encoder) => encoder.flush(),
+            OutputFile::Bzip2(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_pipes_are_stdout_or_named_pipes() {
+        let fifo = std::env::temp_dir().join("db-extractor-output-test.fifo");
+        let _ = fs::remove_file(&fifo);
+        let created = std::process::Command::new("mkfifo").arg(&fifo).status().unwrap();
+        assert!(created.success());
+
+        assert_eq!(Pipe::from_output(Path::new("-")), Some(Pipe::Stdout));
+        assert_eq!(Pipe::from_output(&fifo), Some(Pipe::Fifo(fifo.clone())));
+        assert_eq!(Pipe::from_output(&std::env::temp_dir()), None);
+        assert_eq!(Pipe::from_output(Path::new("./missing")), None);
+
+        fs::remove_file(fifo).unwrap();
+    }
+
+    #[test]
+    fn test_compressed_file_can_be_read_back() {
+        let path = std::env::temp_dir().join("db-extractor-output-test.json.gz");
+
+        let sink = Sink::File(File::create(&path).unwrap());
+        let mut file = OutputFile::new(sink, Some(Compression::Gzip), None).unwrap();
+        file.write_all(b"[{\"id\":1}]").unwrap();
+        file.finish().unwrap();
+
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(File::open(&path).unwrap())
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "[{\"id\":1}]");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_atomic_file_appears_when_finished() {
+        let dir = std::env::temp_dir().join("db-extractor-atomic-test");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("orders.json");
+
+        let mut file = OutputFile::new(Sink::Atomic(AtomicFile::create(&path).unwrap()), None, None)
+            .unwrap();
+        file.write_all(b"[]").unwrap();
+        file.flush().unwrap();
+        assert!(!path.exists());
+        let checksum = file.finish().unwrap();
+
+        assert_eq!(checksum.bytes, 2);
+        assert_eq!(
+            checksum.sha256,
+            "4f53cda18c2baa0c0354bb5f9a3ecbe5ed12ab4d8e11ba873c2f11161202b945"
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[]");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
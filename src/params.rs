@@ -0,0 +1,185 @@
+use anyhow::{anyhow, bail};
+use chrono::{SecondsFormat, Utc};
+use std::collections::HashMap;
+
+use crate::config::DatabaseType;
+
+/// Parses a `name=value` parameter of the command line
+pub fn parse_param(param: &str) -> anyhow::Result<(String, String)> {
+    match param.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_string(), value.to_string())),
+        _ => bail!("parameters must be given as name=value, got {}", param),
+    }
+}
+
+/// Parameters available without passing them on the command line:
+/// `run_date` and `run_timestamp` of the start of the run, in UTC, and
+/// `run_id`, the timestamp in a form usable in file names
+pub fn builtin_params() -> HashMap<String, String> {
+    let now = Utc::now();
+    let mut params = HashMap::new();
+    params.insert("run_date".to_string(), now.format("%Y-%m-%d").to_string());
+    params.insert("run_id".to_string(), now.format("%Y%m%dT%H%M%SZ").to_string());
+    params.insert(
+        "run_timestamp".to_string(),
+        now.to_rfc3339_opts(SecondsFormat::Secs, true),
+    );
+
+    params
+}
+
+/// Replaces the named parameters `:name` in `sql` by the placeholders of the
+/// database and returns the values to bind to them, in order.
+///
+/// Parameters in string literals, quoted identifiers and comments are left
+/// alone, with backslash escapes in the strings of MySQL. `placeholder` gets
+/// the position of the parameter, starting at 1.
+pub fn bind_params(
+    sql: &str,
+    params: &HashMap<String, String>,
+    database_type: &DatabaseType,
+    placeholder: impl Fn(usize) -> String,
+) -> anyhow::Result<(String, Vec<String>)> {
+    let backslash_escapes = matches!(database_type, DatabaseType::MySQL);
+    let chars: Vec<char> = sql.chars().collect();
+    let mut result = String::with_capacity(sql.len());
+    let mut values = Vec::new();
+    let mut idx = 0;
+
+    while idx < chars.len() {
+        let c = chars[idx];
+        let next = chars.get(idx + 1).copied();
+
+        let end = match (c, next) {
+            ('\'', _) | ('"', _) => closing(&chars, idx + 1, &c.to_string(), backslash_escapes),
+            ('`', _) => closing(&chars, idx + 1, "`", false),
+            ('[', _) => closing(&chars, idx + 1, "]", false),
+            ('-', Some('-')) => closing(&chars, idx + 2, "\n", false),
+            ('/', Some('*')) => closing(&chars, idx + 2, "*/", false),
+            (':', Some(n)) if is_param_start(n) && (idx == 0 || chars[idx - 1] != ':') => {
+                let start = idx + 1;
+                let mut end = start;
+                while end < chars.len() && (chars[end].is_alphanumeric() || chars[end] == '_') {
+                    end += 1;
+                }
+
+                let name: String = chars[start..end].iter().collect();
+                let value = params
+                    .get(&name)
+                    .ok_or_else(|| anyhow!("no value given for parameter :{}", name))?;
+                values.push(value.clone());
+                result.push_str(&placeholder(values.len()));
+                idx = end;
+                continue;
+            }
+            _ => idx + 1,
+        };
+
+        result.extend(&chars[idx..end]);
+        idx = end;
+    }
+
+    Ok((result, values))
+}
+
+fn is_param_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+/// Index after the end of a quoted section starting at `start`, which ends
+/// with `delimiter`. Doubled delimiters are escapes in sql, and with
+/// `backslash_escapes` a backslash escapes the next character.
+fn closing(chars: &[char], start: usize, delimiter: &str, backslash_escapes: bool) -> usize {
+    let delimiter: Vec<char> = delimiter.chars().collect();
+    let mut idx = start;
+
+    while idx < chars.len() {
+        if backslash_escapes && chars[idx] == '\\' {
+            idx += 2;
+            continue;
+        }
+        if chars[idx..].starts_with(&delimiter) {
+            let after = idx + delimiter.len();
+            if delimiter.len() == 1 && chars.get(after) == Some(&delimiter[0]) {
+                idx = after + 1;
+                continue;
+            }
+            return after;
+        }
+        idx += 1;
+    }
+
+    chars.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> HashMap<String, String> {
+        let mut params = HashMap::new();
+        params.insert("start_date".to_string(), "2026-01-01".to_string());
+        params.insert("status".to_string(), "open".to_string());
+        params
+    }
+
+    #[test]
+    fn test_bind_params() {
+        let (sql, values) = bind_params(
+            "created_at >= :start_date and status = :status and closed_at > :start_date",
+            &params(),
+            &DatabaseType::MsSQL,
+            |idx| format!("@P{}", idx),
+        )
+        .unwrap();
+
+        assert_eq!(
+            "created_at >= @P1 and status = @P2 and closed_at > @P3",
+            sql
+        );
+        assert_eq!(vec!["2026-01-01", "open", "2026-01-01"], values);
+    }
+
+    #[test]
+    fn test_bind_params_ignores_literals_and_comments() {
+        let sql = "note = 'at 10:00 it''s :status' and [a:b] = 1 -- :status\n\
+                   and x::int = 1 /* :status */ and s = :status";
+        let (result, values) =
+            bind_params(sql, &params(), &DatabaseType::MsSQL, |_| "?".to_string()).unwrap();
+
+        assert_eq!(sql.replace("s = :status", "s = ?"), result);
+        assert_eq!(vec!["open"], values);
+    }
+
+    #[test]
+    fn test_bind_params_skips_backslash_escapes_of_mysql() {
+        let sql = r"note = 'it\'s :status' and s = :status";
+        let (result, values) =
+            bind_params(sql, &params(), &DatabaseType::MySQL, |_| "?".to_string()).unwrap();
+
+        assert_eq!(r"note = 'it\'s :status' and s = ?", result);
+        assert_eq!(vec!["open"], values);
+
+        // a backslash is no escape in sql server
+        let sql = r"note = 'C:\' and s = :status";
+        let (result, _) =
+            bind_params(sql, &params(), &DatabaseType::MsSQL, |_| "?".to_string()).unwrap();
+        assert_eq!(r"note = 'C:\' and s = ?", result);
+    }
+
+    #[test]
+    fn test_bind_params_unknown() {
+        let result = bind_params("id = :id", &params(), &DatabaseType::MySQL, |_| "?".to_string());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_param() {
+        assert_eq!(
+            ("a".to_string(), "b=c".to_string()),
+            parse_param("a=b=c").unwrap()
+        );
+        assert!(parse_param("=b").is_err());
+        assert!(parse_param("a").is_err());
+    }
+}
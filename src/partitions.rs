@@ -0,0 +1,116 @@
+use anyhow::bail;
+
+use crate::config::{DateTruncation, TableConfig};
+use crate::data_types::DataType;
+
+/// Directory of a row like `region=eu/created_at_month=2021-01`, empty if
+/// the table is not partitioned. Values are escaped like Hive does.
+pub fn partition_path(
+    table: &TableConfig,
+    columns: &[String],
+    values: &[DataType],
+) -> anyhow::Result<String> {
+    let mut directories = Vec::new();
+    for partition in &table.partition_by {
+        let idx = match columns.iter().position(|column| column == &partition.column) {
+            Some(idx) => idx,
+            None => bail!(
+                "partition column {} is not a column of table {}",
+                partition.column,
+                table.name
+            ),
+        };
+
+        let (key, value) = match partition.truncate {
+            Some(truncation) => (
+                format!("{}_{}", partition.column, truncation_name(truncation)),
+                truncate(&values[idx], truncation)?,
+            ),
+            None => (partition.column.clone(), values[idx].to_text()),
+        };
+        let value = value.unwrap_or_else(|| "__HIVE_DEFAULT_PARTITION__".to_string());
+
+        directories.push(format!("{}={}", escape(&key), escape(&value)));
+    }
+
+    Ok(directories.join("/"))
+}
+
+fn truncation_name(truncation: DateTruncation) -> &'static str {
+    match truncation {
+        DateTruncation::Year => "year",
+        DateTruncation::Month => "month",
+        DateTruncation::Day => "day",
+    }
+}
+
+fn truncate(value: &DataType, truncation: DateTruncation) -> anyhow::Result<Option<String>> {
+    let pattern = match truncation {
+        DateTruncation::Year => "%Y",
+        DateTruncation::Month => "%Y-%m",
+        DateTruncation::Day => "%Y-%m-%d",
+    };
+
+    Ok(match value {
+        DataType::DateTimeUtc(datetime) => datetime.map(|d| d.format(pattern).to_string()),
+        DataType::DateTime(datetime) => datetime.map(|d| d.format(pattern).to_string()),
+        DataType::Date(date) => date.map(|d| d.format(pattern).to_string()),
+        _ => bail!("only dates can be truncated, not {:?}", value),
+    })
+}
+
+/// Percent encodes characters which are not allowed or special in paths
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if c.is_control() || "\"#%'*/:=?\\\u{7f}{[]^".contains(c) {
+                let mut buffer = [0; 4];
+                c.encode_utf8(&mut buffer)
+                    .bytes()
+                    .map(|byte| format!("%{:02X}", byte))
+                    .collect()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Partition;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_builds_hive_style_path() {
+        let table = TableConfig {
+            name: "orders".to_string(),
+            partition_by: vec![
+                Partition {
+                    column: "region".to_string(),
+                    truncate: None,
+                },
+                Partition {
+                    column: "created_at".to_string(),
+                    truncate: Some(DateTruncation::Month),
+                },
+            ],
+            ..Default::default()
+        };
+        let columns = vec!["region".to_string(), "created_at".to_string()];
+        let created_at = NaiveDate::from_ymd(2021, 1, 15).and_hms(10, 0, 0);
+
+        let path = |region| {
+            partition_path(
+                &table,
+                &columns,
+                &[DataType::String(region), DataType::DateTime(Some(created_at))],
+            )
+            .unwrap()
+        };
+
+        assert_eq!(path(Some("eu/west".to_string())), "region=eu%2Fwest/created_at_month=2021-01");
+        assert_eq!(path(None), "region=__HIVE_DEFAULT_PARTITION__/created_at_month=2021-01");
+    }
+}
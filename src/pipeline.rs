@@ -0,0 +1,191 @@
+use anyhow::Context;
+use std::collections::HashMap;
+
+use crate::binary_files::BinaryFiles;
+use crate::config::{Config, TableConfig};
+use crate::data_types::{BinaryFile, DataType};
+use crate::dates::DateOptions;
+use crate::masking::mask_column;
+use crate::partitions::partition_path;
+use crate::pseudonymization::Pseudonymizer;
+use crate::writer::WriterOptions;
+
+/// Transformations applied to every value after it is read from the database
+/// and before it is handed to the output.
+pub struct ValuePipeline {
+    pseudonymizer: Option<Pseudonymizer>,
+    binary_files: BinaryFiles,
+    dates: DateOptions,
+    /// Date options of columns which override the global ones, by table
+    column_dates: HashMap<String, HashMap<String, DateOptions>>,
+}
+
+impl ValuePipeline {
+    pub fn new(config: &Config, options: &WriterOptions) -> anyhow::Result<ValuePipeline> {
+        let pseudonymizer = if config
+            .tables
+            .iter()
+            .any(|table| !table.pseudonymize.is_empty())
+        {
+            Some(Pseudonymizer::from_env()?)
+        } else {
+            None
+        };
+
+        let mut column_dates = HashMap::new();
+        for table in &config.tables {
+            let mut columns = HashMap::new();
+            for (column, column_options) in &table.column_options {
+                if column_options.date_format.is_some() || column_options.source_timezone.is_some()
+                {
+                    columns.insert(column.clone(), options.dates.for_column(column_options)?);
+                }
+            }
+            column_dates.insert(table.name.clone(), columns);
+        }
+
+        Ok(ValuePipeline {
+            pseudonymizer,
+            binary_files: BinaryFiles::new(options.dir.clone()),
+            dates: options.dates.clone(),
+            column_dates,
+        })
+    }
+
+    /// Runs the pipeline on the values of a row and returns them with the
+    /// partition directory of the row, see [`partition_path`]
+    pub fn apply_row(
+        &self,
+        table: &TableConfig,
+        columns: &[String],
+        values: Vec<DataType>,
+    ) -> anyhow::Result<(String, Vec<DataType>)> {
+        let values = columns
+            .iter()
+            .zip(values)
+            .map(|(column, value)| self.transform(table, column, value))
+            .collect::<anyhow::Result<Vec<DataType>>>()?;
+        let partition = partition_path(table, columns, &values)?;
+        let values = columns
+            .iter()
+            .zip(values)
+            .map(|(column, value)| self.output(table, column, value))
+            .collect::<anyhow::Result<Vec<DataType>>>()?;
+
+        Ok((partition, values))
+    }
+
+    /// Files written for the binary values of `table` since the last call
+    pub fn binary_files(&self, table: &str) -> Vec<BinaryFile> {
+        self.binary_files.take_stored(table)
+    }
+
+    /// Spatial and JSON columns are typed and datetimes converted to UTC first.
+    /// Pseudonymization runs on the raw value and masking rules afterwards.
+    fn transform(
+        &self,
+        table: &TableConfig,
+        column: &str,
+        value: DataType,
+    ) -> anyhow::Result<DataType> {
+        let value = if table.spatial_columns.iter().any(|c| c == column) {
+            value.into_geometry()
+        } else if table.column_options.get(column).is_some_and(|o| o.as_json) {
+            value.into_json().with_context(|| {
+                format!(
+                    "column {} of table {} is not valid JSON",
+                    column, table.name
+                )
+            })?
+        } else {
+            self.dates(table, column).to_utc(value)?
+        };
+        let value = match &self.pseudonymizer {
+            Some(pseudonymizer) if table.pseudonymize.iter().any(|c| c == column) => {
+                pseudonymizer.pseudonymize(value)
+            }
+            _ => value,
+        };
+
+        Ok(mask_column(table, column, value))
+    }
+
+    /// Dates are formatted and binary values moved to files for the output
+    fn output(
+        &self,
+        table: &TableConfig,
+        column: &str,
+        value: DataType,
+    ) -> anyhow::Result<DataType> {
+        match self.dates(table, column).format(value)? {
+            DataType::Binary(Some(bytes)) if table.binary_files => Ok(DataType::BinaryFile(Some(
+                self.binary_files.store(&table.name, &bytes)?,
+            ))),
+            value => Ok(value),
+        }
+    }
+
+    fn dates(&self, table: &TableConfig, column: &str) -> &DateOptions {
+        self.column_dates
+            .get(&table.name)
+            .and_then(|columns| columns.get(column))
+            .unwrap_or(&self.dates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ColumnOptions;
+    use crate::dates::DateFormat;
+
+    fn pipeline() -> ValuePipeline {
+        ValuePipeline {
+            pseudonymizer: None,
+            binary_files: BinaryFiles::new(std::env::temp_dir()),
+            dates: DateOptions {
+                format: DateFormat::Text,
+                source_timezone: None,
+            },
+            column_dates: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_as_json_writes_nested_values() {
+        let mut table = TableConfig {
+            name: "events".to_string(),
+            ..Default::default()
+        };
+        table.column_options.insert(
+            "payload".to_string(),
+            ColumnOptions {
+                as_json: true,
+                ..Default::default()
+            },
+        );
+        let columns = vec!["payload".to_string()];
+        let pipeline = pipeline();
+
+        let (_, values) = pipeline
+            .apply_row(
+                &table,
+                &columns,
+                vec![DataType::String(Some(r#"{"b": [1], "a": 2}"#.to_string()))],
+            )
+            .unwrap();
+        assert_eq!(serde_json::to_string(&values).unwrap(), r#"[{"b":[1],"a":2}]"#);
+
+        let error = pipeline
+            .apply_row(
+                &table,
+                &columns,
+                vec![DataType::String(Some("{".to_string()))],
+            )
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "column payload of table events is not valid JSON"
+        );
+    }
+}
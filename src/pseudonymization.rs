@@ -0,0 +1,184 @@
+use anyhow::Context;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use std::convert::TryFrom;
+
+use crate::data_types::DataType;
+
+/// Environment variable holding the secret key used for pseudonymization
+pub const PSEUDONYMIZATION_KEY_ENV: &str = "DB_EXTRACTOR_PSEUDONYMIZATION_KEY";
+
+/// Replaces values by tokens derived from a keyed HMAC of the value.
+///
+/// The same value always maps to the same token as long as the key stays the
+/// same, regardless of the table or column it comes from, so pseudonymized
+/// extracts can still be joined.
+pub struct Pseudonymizer {
+    key: Vec<u8>,
+}
+
+impl Pseudonymizer {
+    pub fn new(key: &[u8]) -> Pseudonymizer {
+        Pseudonymizer { key: key.to_vec() }
+    }
+
+    pub fn from_env() -> anyhow::Result<Pseudonymizer> {
+        let key = std::env::var(PSEUDONYMIZATION_KEY_ENV).with_context(|| {
+            format!(
+                "{} must be set to pseudonymize columns",
+                PSEUDONYMIZATION_KEY_ENV
+            )
+        })?;
+
+        Ok(Pseudonymizer::new(key.as_bytes()))
+    }
+
+    /// The token is derived from the text of the value, so the same id gives
+    /// the same token in int, bigint and text columns. Integers are permuted
+    /// within the integers of their sign and size, so INT columns stay within
+    /// INT and keys stay unique. Other values become a hex token, nulls are
+    /// kept as they are.
+    pub fn pseudonymize(&self, value: DataType) -> DataType {
+        let text = match value.to_text() {
+            Some(text) => text,
+            None => return value,
+        };
+
+        let token = match text.parse::<i64>() {
+            Ok(number) if number.to_string() == text => self.permute_integer(number),
+            _ => return DataType::String(Some(hex::encode(&self.mac(&[text.as_bytes()])[..16]))),
+        };
+        match value {
+            // the token of an INT value is within INT
+            DataType::Int(_) => DataType::Int(Some(token as i32)),
+            DataType::BigInt(_) => DataType::BigInt(Some(token)),
+            _ => DataType::String(Some(token.to_string())),
+        }
+    }
+
+    /// Bijection of the i64 values keeping the sign and whether the value fits
+    /// into an i32
+    fn permute_integer(&self, number: i64) -> i64 {
+        let fits_i32 = i32::try_from(number).is_ok();
+        let permute = |value: u64| {
+            if fits_i32 {
+                self.cycle_walk(value, 32, |token| token < 1 << 31)
+            } else {
+                self.cycle_walk(value, 64, |token| (1 << 31..1 << 63).contains(&token))
+            }
+        };
+
+        if number >= 0 {
+            permute(number as u64) as i64
+        } else {
+            -1 - permute((-1 - number) as u64) as i64
+        }
+    }
+
+    /// Applies the permutation of `bits` bit values until the result is in
+    /// the range of `value`, which makes it a permutation of that range
+    fn cycle_walk(&self, value: u64, bits: u32, in_range: impl Fn(u64) -> bool) -> u64 {
+        let mut token = self.feistel(value, bits);
+        while !in_range(token) {
+            token = self.feistel(token, bits);
+        }
+        token
+    }
+
+    /// Keyed permutation of the values with `bits` bits, a Feistel network
+    /// with HMAC as round function
+    fn feistel(&self, value: u64, bits: u32) -> u64 {
+        let half = bits / 2;
+        let mask = u64::MAX >> (64 - half);
+        let (mut left, mut right) = ((value >> half) & mask, value & mask);
+        for round in 0..4u8 {
+            let digest = self.mac(&[&[bits as u8, round], &right.to_be_bytes()]);
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&digest[..8]);
+            let next = left ^ (u64::from_be_bytes(bytes) & mask);
+            left = right;
+            right = next;
+        }
+        (left << half) | right
+    }
+
+    fn mac(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        for part in parts {
+            mac.update(part);
+        }
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pseudonymize_is_consistent() {
+        let pseudonymizer = Pseudonymizer::new(b"secret");
+        let other = Pseudonymizer::new(b"other secret");
+
+        let token = pseudonymizer
+            .pseudonymize(DataType::Int(Some(42)))
+            .to_text();
+        assert_eq!(
+            token,
+            pseudonymizer
+                .pseudonymize(DataType::BigInt(Some(42)))
+                .to_text()
+        );
+        assert_ne!(token, other.pseudonymize(DataType::Int(Some(42))).to_text());
+        assert_ne!(
+            token,
+            pseudonymizer
+                .pseudonymize(DataType::Int(Some(43)))
+                .to_text()
+        );
+
+        assert_eq!(
+            token,
+            pseudonymizer
+                .pseudonymize(DataType::String(Some("42".to_string())))
+                .to_text()
+        );
+
+        let token = pseudonymizer
+            .pseudonymize(DataType::String(Some("jane@example.com".to_string())))
+            .to_text()
+            .unwrap();
+        assert_eq!(32, token.len());
+        assert_eq!(
+            None,
+            pseudonymizer.pseudonymize(DataType::String(None)).to_text()
+        );
+    }
+
+    #[test]
+    fn test_integers_keep_their_range() {
+        let pseudonymizer = Pseudonymizer::new(b"secret");
+
+        for number in &[0, 1, -1, i32::MAX, i32::MIN] {
+            match pseudonymizer.pseudonymize(DataType::Int(Some(*number))) {
+                DataType::Int(Some(token)) => assert_eq!(*number >= 0, token >= 0),
+                value => panic!("expected an int, got {:?}", value),
+            }
+        }
+        for number in &[i64::MAX, i64::MIN, 1 << 40] {
+            match pseudonymizer.pseudonymize(DataType::BigInt(Some(*number))) {
+                DataType::BigInt(Some(token)) => {
+                    assert!(i32::try_from(token).is_err());
+                    assert_eq!(*number >= 0, token >= 0);
+                }
+                value => panic!("expected a bigint, got {:?}", value),
+            }
+        }
+
+        let tokens: std::collections::HashSet<i64> = (0..1000)
+            .map(|number| pseudonymizer.permute_integer(number))
+            .collect();
+        assert_eq!(1000, tokens.len());
+    }
+}
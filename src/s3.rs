@@ -0,0 +1,590 @@
+use anyhow::{bail, Context};
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+use std::io::{self, Write};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Size of the parts of multipart uploads. S3 allows at most 10000 parts, so
+/// files can get as large as 160 GiB.
+const PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// Wait before an upload that failed with a server or connection error is
+/// sent again
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Bucket and key prefix of `--output s3://bucket/prefix`, with the
+/// credentials taken from the usual `AWS_*` environment variables
+#[derive(Debug)]
+pub struct S3Output {
+    pub bucket: String,
+    pub prefix: String,
+    /// Endpoint of S3 compatible storage like MinIO, addressed path style
+    pub endpoint: Option<String>,
+    pub region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl S3Output {
+    /// `None` if the output is not an `s3://` url
+    pub fn from_output(output: &str, endpoint: Option<String>) -> anyhow::Result<Option<S3Output>> {
+        Self::from_env(output, endpoint, |name| std::env::var(name).ok())
+    }
+
+    /// Like `from_output`, with the AWS_* variables looked up by `var`
+    fn from_env(
+        output: &str,
+        endpoint: Option<String>,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> anyhow::Result<Option<S3Output>> {
+        let location = match output.strip_prefix("s3://") {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+        let (bucket, prefix) = match location.find('/') {
+            Some(slash) => (&location[..slash], location[slash + 1..].trim_end_matches('/')),
+            None => (location, ""),
+        };
+        if bucket.is_empty() {
+            bail!("{} has no bucket", output);
+        }
+
+        let env = |name: &str| var(name).filter(|value| !value.is_empty());
+        Ok(Some(S3Output {
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+            endpoint: endpoint
+                .or_else(|| env("AWS_ENDPOINT_URL"))
+                .map(|endpoint| endpoint.trim_end_matches('/').to_string()),
+            region: env("AWS_REGION")
+                .or_else(|| env("AWS_DEFAULT_REGION"))
+                .unwrap_or_else(|| "us-east-1".to_string()),
+            access_key: env("AWS_ACCESS_KEY_ID")
+                .context("AWS_ACCESS_KEY_ID must be set to write to S3")?,
+            secret_key: env("AWS_SECRET_ACCESS_KEY")
+                .context("AWS_SECRET_ACCESS_KEY must be set to write to S3")?,
+            session_token: env("AWS_SESSION_TOKEN"),
+        }))
+    }
+
+    /// Key of a file below the prefix
+    pub fn key(&self, file_name: &str) -> String {
+        if self.prefix.is_empty() {
+            file_name.to_string()
+        } else {
+            format!("{}/{}", self.prefix, file_name)
+        }
+    }
+
+    /// Scheme and host of the requests and the path of an object, or of the
+    /// bucket if `key` is empty
+    fn object_url(&self, key: &str) -> (String, String, String) {
+        match &self.endpoint {
+            Some(endpoint) => {
+                let (scheme, host) = match endpoint.find("://") {
+                    Some(separator) => (&endpoint[..separator], &endpoint[separator + 3..]),
+                    None => ("https", endpoint.as_str()),
+                };
+                let bucket = uri_encode(&self.bucket, false);
+                let path = format!("/{}/{}", bucket, uri_encode(key, false));
+                (scheme.to_string(), host.to_string(), path)
+            }
+            None => (
+                "https".to_string(),
+                format!("{}.s3.{}.amazonaws.com", self.bucket, self.region),
+                format!("/{}", uri_encode(key, false)),
+            ),
+        }
+    }
+
+    /// Sends a request signed with AWS signature version 4
+    fn request(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        body: &[u8],
+    ) -> anyhow::Result<ureq::Response> {
+        let (request, url) = self.signed_request(method, key, query, body);
+        response(method, &url, request.send_bytes(body))
+    }
+
+    /// Like [`S3Output::request`], but sent a second time if it fails with a
+    /// server error or a broken connection, for uploads which may be repeated
+    fn request_with_retry(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        body: &[u8],
+    ) -> anyhow::Result<ureq::Response> {
+        let (request, url) = self.signed_request(method, key, query, body);
+        match request.send_bytes(body) {
+            Err(ureq::Error::Status(status, _)) if status >= 500 => {}
+            Err(ureq::Error::Transport(_)) => {}
+            result => return response(method, &url, result),
+        }
+        thread::sleep(RETRY_DELAY);
+
+        self.request(method, key, query, body)
+    }
+
+    /// Whether an object exists under `key`
+    pub fn exists(&self, key: &str) -> anyhow::Result<bool> {
+        let (request, url) = self.signed_request("HEAD", key, &[], b"");
+        match request.call() {
+            Ok(_) => Ok(true),
+            Err(ureq::Error::Status(404, _)) => Ok(false),
+            Err(ureq::Error::Status(status, _)) => {
+                bail!("HEAD {} failed with status {}", url, status)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Whether there are objects with keys starting with `prefix`
+    pub fn prefix_exists(&self, prefix: &str) -> anyhow::Result<bool> {
+        let query = [("list-type", "2"), ("max-keys", "1"), ("prefix", prefix)];
+        let response = self.request("GET", "", &query, b"")?;
+        let count = xml_element(&response.into_string()?, "KeyCount")
+            .context("response to list objects has no KeyCount")?;
+        Ok(count != "0")
+    }
+
+    /// Keys of the objects starting with `prefix`
+    pub fn list_keys(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut continuation: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if let Some(token) = &continuation {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let response = self.request("GET", "", &query, b"")?.into_string()?;
+            keys.extend(xml_elements(&response, "Key").into_iter().map(|key| xml_unescape(&key)));
+            continuation = xml_element(&response, "NextContinuationToken");
+            if continuation.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
+    pub fn get(&self, key: &str) -> anyhow::Result<String> {
+        Ok(self.request("GET", key, &[], b"")?.into_string()?)
+    }
+
+    pub fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.request("DELETE", key, &[], b"")?;
+        Ok(())
+    }
+
+    /// Request with the headers of AWS signature version 4, and its url
+    fn signed_request(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        body: &[u8],
+    ) -> (ureq::Request, String) {
+        let (scheme, host, path) = self.object_url(key);
+        let now = Utc::now();
+        let date = now.format("%Y%m%d").to_string();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let payload_hash = hex::encode(Sha256::digest(body));
+
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join("&");
+
+        let mut headers = vec![
+            ("host", host.clone()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", timestamp.clone()),
+        ];
+        if let Some(token) = &self.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<&str>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, path, query, canonical_headers, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut key = hmac_sha256(format!("AWS4{}", self.secret_key).as_bytes(), &date);
+        for part in &[self.region.as_str(), "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part);
+        }
+        let signature = hex::encode(hmac_sha256(&key, &string_to_sign));
+
+        let url = if query.is_empty() {
+            format!("{}://{}{}", scheme, host, path)
+        } else {
+            format!("{}://{}{}?{}", scheme, host, path, query)
+        };
+        let mut request = ureq::request(method, &url).set(
+            "Authorization",
+            &format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key, scope, signed_headers, signature
+            ),
+        );
+        for (name, value) in &headers[1..] {
+            request = request.set(name, value);
+        }
+
+        (request, url)
+    }
+}
+
+fn response(
+    method: &str,
+    url: &str,
+    result: Result<ureq::Response, ureq::Error>,
+) -> anyhow::Result<ureq::Response> {
+    match result {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(status, response)) => bail!(
+            "{} {} failed with status {}: {}",
+            method,
+            url,
+            status,
+            response.into_string().unwrap_or_default()
+        ),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent encoding of S3, keeping slashes in paths
+fn uri_encode(text: &str, encode_slash: bool) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            b'/' if !encode_slash => "/".to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Object written as it is produced. Files up to the part size are put in a
+/// single request, larger ones are uploaded in parts by a thread of their own,
+/// so the rows of the next part are read while a part is uploaded.
+pub struct S3Upload {
+    output: Arc<S3Output>,
+    key: String,
+    buffer: Vec<u8>,
+    parts: Option<(SyncSender<UploadMessage>, JoinHandle<anyhow::Result<()>>)>,
+}
+
+enum UploadMessage {
+    Part(Vec<u8>),
+    Complete,
+}
+
+impl S3Upload {
+    pub fn new(output: Arc<S3Output>, key: String) -> S3Upload {
+        S3Upload {
+            output,
+            key,
+            buffer: Vec::new(),
+            parts: None,
+        }
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        match self.parts.take() {
+            None => {
+                let (output, key, body) = (&self.output, &self.key, &self.buffer);
+                tokio::task::block_in_place(|| output.request_with_retry("PUT", key, &[], body))?;
+            }
+            Some((sender, uploader)) => {
+                if !self.buffer.is_empty() {
+                    let part = std::mem::take(&mut self.buffer);
+                    let _ = send(&sender, UploadMessage::Part(part));
+                }
+                let _ = send(&sender, UploadMessage::Complete);
+                tokio::task::block_in_place(|| join(uploader))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn upload_part(&mut self) -> anyhow::Result<()> {
+        let part = std::mem::take(&mut self.buffer);
+        let (output, key) = (self.output.clone(), self.key.clone());
+        let (sender, _) = self.parts.get_or_insert_with(|| {
+            // one part is buffered while the one before is uploaded
+            let (sender, receiver) = sync_channel(1);
+            let uploader = thread::spawn(move || multipart_upload(&output, &key, receiver));
+            (sender, uploader)
+        });
+        if send(sender, UploadMessage::Part(part)).is_err() {
+            // the upload failed, its error is returned by the thread
+            let (_, uploader) = self.parts.take().unwrap();
+            join(uploader)?;
+            bail!("upload of {} stopped", self.key);
+        }
+
+        Ok(())
+    }
+}
+
+/// Waits for the uploader thread if the channel is full, without blocking
+/// other tasks of the runtime
+fn send(
+    sender: &SyncSender<UploadMessage>,
+    message: UploadMessage,
+) -> Result<(), std::sync::mpsc::SendError<UploadMessage>> {
+    tokio::task::block_in_place(|| sender.send(message))
+}
+
+fn join(uploader: JoinHandle<anyhow::Result<()>>) -> anyhow::Result<()> {
+    match uploader.join() {
+        Ok(result) => result,
+        Err(_) => bail!("the S3 upload thread panicked"),
+    }
+}
+
+/// Uploads the parts until the upload is completed. If the channel is closed
+/// before, because writing the file failed, the upload is aborted so its parts
+/// do not stay in the bucket.
+fn multipart_upload(
+    output: &S3Output,
+    key: &str,
+    messages: Receiver<UploadMessage>,
+) -> anyhow::Result<()> {
+    let response = output.request("POST", key, &[("uploads", "")], b"")?;
+    let upload_id = xml_element(&response.into_string()?, "UploadId")
+        .context("response to create a multipart upload has no UploadId")?;
+    let abort = || {
+        let _ = output.request("DELETE", key, &[("uploadId", &upload_id)], b"");
+    };
+
+    let mut etags = Vec::new();
+    loop {
+        match messages.recv() {
+            Ok(UploadMessage::Part(part)) => {
+                let part_number = (etags.len() + 1).to_string();
+                let query = [("partNumber", part_number.as_str()), ("uploadId", &upload_id)];
+                let etag = output
+                    .request_with_retry("PUT", key, &query, &part)
+                    .and_then(|response| {
+                        let etag = response
+                            .header("ETag")
+                            .context("response to a part upload has no ETag")?;
+                        Ok(etag.to_string())
+                    });
+                match etag {
+                    Ok(etag) => etags.push(etag),
+                    Err(err) => {
+                        abort();
+                        return Err(err);
+                    }
+                }
+            }
+            Ok(UploadMessage::Complete) => break,
+            Err(_) => {
+                abort();
+                return Ok(());
+            }
+        }
+    }
+
+    let parts: String = etags
+        .iter()
+        .enumerate()
+        .map(|(idx, etag)| {
+            format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                idx + 1,
+                etag
+            )
+        })
+        .collect();
+    let body = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts);
+    if let Err(err) = output.request("POST", key, &[("uploadId", &upload_id)], body.as_bytes()) {
+        abort();
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+impl Write for S3Upload {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= PART_SIZE {
+            self.upload_part()
+                .map_err(|err| io::Error::other(err.to_string()))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Uploads which are not finished, because writing the file failed, are
+/// aborted by their thread when the channel closes
+impl Drop for S3Upload {
+    fn drop(&mut self) {
+        if let Some((sender, uploader)) = self.parts.take() {
+            drop(sender);
+            let _ = tokio::task::block_in_place(|| uploader.join());
+        }
+    }
+}
+
+fn xml_element(xml: &str, name: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    Some(xml[start..end].to_string())
+}
+
+fn xml_elements(mut xml: &str, name: &str) -> Vec<String> {
+    let mut elements = Vec::new();
+    while let Some(element) = xml_element(xml, name) {
+        let end = xml.find(&format!("</{}>", name)).unwrap_or_default() + name.len() + 3;
+        elements.push(element);
+        xml = &xml[end..];
+    }
+    elements
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_output_url() {
+        let env = |name: &str| match name {
+            "AWS_ACCESS_KEY_ID" => Some("key".to_string()),
+            "AWS_SECRET_ACCESS_KEY" => Some("secret".to_string()),
+            "AWS_ENDPOINT_URL" => Some("http://localhost:9000/".to_string()),
+            _ => None,
+        };
+
+        let output = S3Output::from_env("s3://extracts/daily/", None, env)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(output.bucket, "extracts");
+        assert_eq!(output.key("orders.json"), "daily/orders.json");
+        assert_eq!(output.endpoint.as_deref(), Some("http://localhost:9000"));
+        assert_eq!(output.region, "us-east-1");
+        assert!(S3Output::from_env("/tmp", None, env).unwrap().is_none());
+        assert!(S3Output::from_env("s3://extracts", None, |_| None).is_err());
+    }
+
+    /// Answers the requests with the given statuses and returns the methods
+    /// of the requests
+    fn serve(statuses: Vec<u16>) -> (String, thread::JoinHandle<Vec<String>>) {
+        use std::io::{BufRead, BufReader, Read};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut methods = Vec::new();
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut content_length = 0;
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                methods.push(line.split(' ').next().unwrap().to_string());
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(length) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                }
+                reader.read_exact(&mut vec![0; content_length]).unwrap();
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\nETag: \"1\"\r\nContent-Length: 0\r\n\r\n",
+                    status
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            methods
+        });
+
+        (endpoint, server)
+    }
+
+    fn output(endpoint: String) -> S3Output {
+        S3Output {
+            bucket: "extracts".to_string(),
+            prefix: String::new(),
+            endpoint: Some(endpoint),
+            region: "us-east-1".to_string(),
+            access_key: "key".to_string(),
+            secret_key: "secret".to_string(),
+            session_token: None,
+        }
+    }
+
+    #[test]
+    fn test_uploads_are_retried_once_after_server_errors() {
+        let (endpoint, server) = serve(vec![503, 200, 500, 500]);
+        let output = output(endpoint);
+
+        assert!(output.request_with_retry("PUT", "orders.json", &[], b"[]").is_ok());
+        assert!(output.request_with_retry("PUT", "orders.json", &[], b"[]").is_err());
+        assert_eq!(server.join().unwrap(), vec!["PUT"; 4]);
+    }
+
+    #[test]
+    fn test_encodes_like_s3() {
+        assert_eq!(uri_encode("a b/c~d", false), "a%20b/c~d");
+        assert_eq!(uri_encode("a b/c~d", true), "a%20b%2Fc~d");
+    }
+}
@@ -0,0 +1,314 @@
+use anyhow::{anyhow, bail};
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::config::TableConfig;
+use crate::data_types::DataType;
+
+const SPATIAL_TYPES: [&str; 10] = [
+    "geometry",
+    "geography",
+    "point",
+    "linestring",
+    "polygon",
+    "multipoint",
+    "multilinestring",
+    "multipolygon",
+    "geometrycollection",
+    "geomcollection",
+];
+
+/// Configured spatial columns of `table` and its columns with a spatial type
+/// in `schema`
+pub fn spatial_columns(table: &TableConfig, schema: &IndexMap<String, String>) -> Vec<String> {
+    table
+        .columns
+        .iter()
+        .filter(|column| {
+            table.spatial_columns.contains(column)
+                || schema.iter().any(|(name, data_type)| {
+                    name.eq_ignore_ascii_case(column)
+                        && SPATIAL_TYPES
+                            .iter()
+                            .any(|spatial| data_type.eq_ignore_ascii_case(spatial))
+                })
+        })
+        .cloned()
+        .collect()
+}
+
+/// Comma separated list of the quoted columns of `table`, with spatial columns
+/// selected as WKT under their own name
+pub fn select_list(
+    table: &TableConfig,
+    quote: fn(&str) -> String,
+    as_wkt: fn(&str) -> String,
+) -> String {
+    table
+        .columns
+        .iter()
+        .map(|column| {
+            if table.spatial_columns.contains(column) {
+                format!("{} AS {}", as_wkt(&quote(column)), quote(column))
+            } else {
+                quote(column)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Row of a GeoJSON `FeatureCollection`
+#[derive(Serialize)]
+pub struct Feature<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    geometry: DataType,
+    properties: IndexMap<&'a str, DataType>,
+}
+
+/// The first spatial column of `table` becomes the geometry of the feature,
+/// all other columns its properties
+pub fn feature<'a>(table: &TableConfig, mut row: IndexMap<&'a str, DataType>) -> Feature<'a> {
+    let geometry = table
+        .spatial_columns
+        .first()
+        .and_then(|column| row.shift_remove(column.as_str()))
+        .unwrap_or(DataType::Geometry(None));
+
+    Feature {
+        kind: "Feature",
+        geometry,
+        properties: row,
+    }
+}
+
+/// GeoJSON geometry object of a WKT value
+pub fn wkt_to_geojson(wkt: &str) -> anyhow::Result<Value> {
+    let mut parser = WktParser {
+        tokens: tokenize(wkt)?,
+        position: 0,
+        measured: false,
+    };
+    let geometry = parser.geometry()?;
+    if parser.position < parser.tokens.len() {
+        bail!("unexpected input after geometry in {}", wkt);
+    }
+
+    Ok(geometry)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Comma,
+    Word(String),
+    Number(f64),
+}
+
+fn tokenize(wkt: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = wkt.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' || c == ')' || c == ',' {
+            chars.next();
+            tokens.push(match c {
+                '(' => Token::Open,
+                ')' => Token::Close,
+                _ => Token::Comma,
+            });
+        } else if c.is_ascii_alphabetic() {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphabetic()) {
+                word.push(c.to_ascii_uppercase());
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        } else if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_digit() || "+-.eE".contains(**c))
+            {
+                number.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Number(
+                number
+                    .parse()
+                    .map_err(|_| anyhow!("invalid number {} in {}", number, wkt))?,
+            ));
+        } else {
+            bail!("unexpected character {} in {}", c, wkt);
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct WktParser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// The coordinates of the current geometry have a measure, which GeoJSON
+    /// has no place for, so it is dropped
+    measured: bool,
+}
+
+impl WktParser {
+    fn geometry(&mut self) -> anyhow::Result<Value> {
+        let kind = match self.tokens.get(self.position) {
+            Some(Token::Word(word)) => word.clone(),
+            token => bail!("expected geometry type, found {:?}", token),
+        };
+        self.position += 1;
+
+        self.measured = false;
+        if let Some(Token::Word(dimension)) = self.tokens.get(self.position) {
+            if dimension == "Z" || dimension == "M" || dimension == "ZM" {
+                self.measured = dimension == "M";
+                self.position += 1;
+            }
+        }
+        let empty = self.tokens.get(self.position) == Some(&Token::Word("EMPTY".to_string()));
+        if empty {
+            self.position += 1;
+        }
+
+        let coordinates = match kind.as_str() {
+            "GEOMETRYCOLLECTION" | "GEOMCOLLECTION" => {
+                let geometries = if empty {
+                    Vec::new()
+                } else {
+                    self.list(WktParser::geometry)?
+                };
+                return Ok(json!({ "type": "GeometryCollection", "geometries": geometries }));
+            }
+            _ if empty => json!([]),
+            "POINT" => {
+                self.expect(Token::Open)?;
+                let point = self.coordinate()?;
+                self.expect(Token::Close)?;
+                point
+            }
+            "LINESTRING" => json!(self.list(WktParser::coordinate)?),
+            "POLYGON" | "MULTILINESTRING" => json!(self.list(WktParser::line)?),
+            "MULTIPOINT" => json!(self.list(WktParser::point)?),
+            "MULTIPOLYGON" => json!(self.list(WktParser::polygon)?),
+            _ => bail!("geometry type {} is not supported", kind),
+        };
+
+        Ok(json!({ "type": geojson_type(&kind)?, "coordinates": coordinates }))
+    }
+
+    fn coordinate(&mut self) -> anyhow::Result<Value> {
+        let mut numbers = Vec::new();
+        while let Some(Token::Number(number)) = self.tokens.get(self.position) {
+            numbers.push(*number);
+            self.position += 1;
+        }
+        if numbers.len() < 2 {
+            bail!("coordinate needs at least two numbers");
+        }
+        numbers.truncate(if self.measured { 2 } else { 3 });
+
+        Ok(json!(numbers))
+    }
+
+    /// Points of a multi point may or may not be enclosed in parentheses
+    fn point(&mut self) -> anyhow::Result<Value> {
+        if self.tokens.get(self.position) == Some(&Token::Open) {
+            self.position += 1;
+            let point = self.coordinate()?;
+            self.expect(Token::Close)?;
+            Ok(point)
+        } else {
+            self.coordinate()
+        }
+    }
+
+    fn line(&mut self) -> anyhow::Result<Value> {
+        Ok(json!(self.list(WktParser::coordinate)?))
+    }
+
+    fn polygon(&mut self) -> anyhow::Result<Value> {
+        Ok(json!(self.list(WktParser::line)?))
+    }
+
+    /// Comma separated items enclosed in parentheses
+    fn list(
+        &mut self,
+        item: fn(&mut WktParser) -> anyhow::Result<Value>,
+    ) -> anyhow::Result<Vec<Value>> {
+        self.expect(Token::Open)?;
+        let mut items = vec![item(self)?];
+        while self.tokens.get(self.position) == Some(&Token::Comma) {
+            self.position += 1;
+            items.push(item(self)?);
+        }
+        self.expect(Token::Close)?;
+
+        Ok(items)
+    }
+
+    fn expect(&mut self, token: Token) -> anyhow::Result<()> {
+        match self.tokens.get(self.position) {
+            Some(found) if *found == token => {
+                self.position += 1;
+                Ok(())
+            }
+            found => bail!("expected {:?}, found {:?}", token, found),
+        }
+    }
+}
+
+fn geojson_type(kind: &str) -> anyhow::Result<&'static str> {
+    match kind {
+        "POINT" => Ok("Point"),
+        "LINESTRING" => Ok("LineString"),
+        "POLYGON" => Ok("Polygon"),
+        "MULTIPOINT" => Ok("MultiPoint"),
+        "MULTILINESTRING" => Ok("MultiLineString"),
+        "MULTIPOLYGON" => Ok("MultiPolygon"),
+        _ => bail!("geometry type {} is not supported", kind),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_converts_wkt_to_geojson() {
+        assert_eq!(
+            wkt_to_geojson("POINT (30 10)").unwrap(),
+            json!({ "type": "Point", "coordinates": [30.0, 10.0] })
+        );
+        assert_eq!(
+            wkt_to_geojson("POLYGON ((30 10, 40 40, 20 40, 30 10))").unwrap(),
+            json!({
+                "type": "Polygon",
+                "coordinates": [[[30.0, 10.0], [40.0, 40.0], [20.0, 40.0], [30.0, 10.0]]]
+            })
+        );
+        assert_eq!(
+            wkt_to_geojson("MULTIPOINT ((10 40), (40 30))").unwrap(),
+            wkt_to_geojson("MULTIPOINT (10 40, 40 30)").unwrap()
+        );
+        assert_eq!(
+            wkt_to_geojson("GEOMETRYCOLLECTION (POINT M (1 2 3), LINESTRING EMPTY)").unwrap(),
+            json!({
+                "type": "GeometryCollection",
+                "geometries": [
+                    { "type": "Point", "coordinates": [1.0, 2.0] },
+                    { "type": "LineString", "coordinates": [] }
+                ]
+            })
+        );
+        assert!(wkt_to_geojson("CIRCULARSTRING (1 1, 2 0, 1 -1)").is_err());
+    }
+}
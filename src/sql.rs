@@ -1,11 +1,37 @@
-use crate::data_types::DataType;
+use crate::data_types::{BinaryEncoding, DataType};
 
-pub fn sql_to_string(data: &[DataType]) -> anyhow::Result<String> {
+pub fn sql_to_string(data: &[DataType], binary_encoding: BinaryEncoding) -> anyhow::Result<String> {
     Ok(format!(
         "({})",
         data.iter()
-            .map(|e| serde_json::to_string(e).unwrap())
+            .map(|e| match e {
+                // a file reference is loaded back by path
+                DataType::BinaryFile(Some(file)) => serde_json::to_string(&file.path).unwrap(),
+                DataType::Geometry(Some(text)) => serde_json::to_string(text).unwrap(),
+                DataType::Json(Some(json)) => serde_json::to_string(&json.to_string()).unwrap(),
+                DataType::Binary(Some(bytes)) if binary_encoding == BinaryEncoding::Literal => {
+                    binary_encoding.encode(bytes)
+                }
+                DataType::Binary(Some(bytes)) => {
+                    serde_json::to_string(&binary_encoding.encode(bytes)).unwrap()
+                }
+                _ => serde_json::to_string(e).unwrap(),
+            })
             .collect::<Vec<String>>()
             .join(",")
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_values_use_encoding() {
+        let data = [DataType::Binary(Some(vec![0xca, 0xfe])), DataType::Binary(None)];
+
+        assert_eq!(sql_to_string(&data, BinaryEncoding::Literal).unwrap(), "(0xCAFE,null)");
+        assert_eq!(sql_to_string(&data, BinaryEncoding::Hex).unwrap(), "(\"cafe\",null)");
+        assert_eq!(sql_to_string(&data, BinaryEncoding::Base64).unwrap(), "(\"yv4=\",null)");
+    }
+}
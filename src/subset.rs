@@ -0,0 +1,274 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::config::TableConfig;
+use crate::foreign_keys::ForeignKey;
+
+/// Computes the filter of every table for a referentially complete subset.
+///
+/// Tables with a `where_clause` are the seeds of the subset. Rows of other
+/// tables are extracted if they reference a selected row (children), or if a
+/// selected row references them (parents). Tables not reached from any seed
+/// are filtered out completely. Cyclic relationships are only followed once.
+///
+/// `table_ref` turns a table name into the reference used in a `FROM` clause,
+/// `quote` quotes identifiers.
+pub fn subset_filters(
+    tables: &[TableConfig],
+    foreign_keys: &[ForeignKey],
+    table_ref: &dyn Fn(&str) -> String,
+    quote: fn(&str) -> String,
+) -> HashMap<String, String> {
+    let subset = Subset::new(tables, foreign_keys, table_ref, quote);
+
+    tables
+        .iter()
+        .map(|table| {
+            let filter = if subset.included.contains(table.name.as_str()) {
+                subset.filter(&table.name, &mut Vec::new())
+            } else {
+                None
+            };
+            (
+                table.name.clone(),
+                filter.unwrap_or_else(|| "1=0".to_string()),
+            )
+        })
+        .collect()
+}
+
+struct Subset<'a> {
+    seeds: HashMap<&'a str, &'a str>,
+    foreign_keys: Vec<&'a ForeignKey>,
+    /// seeds and all tables referencing them, directly or indirectly
+    children: HashSet<&'a str>,
+    /// `children` and all tables referenced by them
+    included: HashSet<&'a str>,
+    table_ref: &'a dyn Fn(&str) -> String,
+    quote: fn(&str) -> String,
+}
+
+impl<'a> Subset<'a> {
+    fn new(
+        tables: &'a [TableConfig],
+        foreign_keys: &'a [ForeignKey],
+        table_ref: &'a dyn Fn(&str) -> String,
+        quote: fn(&str) -> String,
+    ) -> Subset<'a> {
+        let names: HashSet<&str> = tables.iter().map(|table| table.name.as_str()).collect();
+        let foreign_keys: Vec<&ForeignKey> = foreign_keys
+            .iter()
+            .filter(|fk| {
+                fk.table != fk.referenced_table
+                    && names.contains(fk.table.as_str())
+                    && names.contains(fk.referenced_table.as_str())
+            })
+            .collect();
+
+        let seeds: HashMap<&str, &str> = tables
+            .iter()
+            .filter_map(|table| {
+                table
+                    .where_clause
+                    .as_ref()
+                    .map(|where_clause| (table.name.as_str(), where_clause.as_str()))
+            })
+            .collect();
+
+        let children = reachable(seeds.keys().copied().collect(), |table| {
+            foreign_keys
+                .iter()
+                .filter(|fk| fk.referenced_table == table)
+                .map(|fk| fk.table.as_str())
+                .collect()
+        });
+        let included = reachable(children.clone(), |table| {
+            foreign_keys
+                .iter()
+                .filter(|fk| fk.table == table)
+                .map(|fk| fk.referenced_table.as_str())
+                .collect()
+        });
+
+        Subset {
+            seeds,
+            foreign_keys,
+            children,
+            included,
+            table_ref,
+            quote,
+        }
+    }
+
+    /// Rows that are seeds or reference selected rows of a parent
+    fn child_filter(&self, table: &'a str, path: &mut Vec<&'a str>) -> Option<String> {
+        if let Some(where_clause) = self.seeds.get(table) {
+            return Some((*where_clause).to_string());
+        }
+
+        let mut conditions = Vec::new();
+        path.push(table);
+        for fk in &self.foreign_keys {
+            if fk.table == table
+                && self.children.contains(fk.referenced_table.as_str())
+                && !path.contains(&fk.referenced_table.as_str())
+            {
+                if let Some(filter) = self.child_filter(&fk.referenced_table, path) {
+                    conditions.push(self.exists(
+                        &fk.referenced_table,
+                        &fk.referenced_columns,
+                        table,
+                        &fk.columns,
+                        &filter,
+                    ));
+                }
+            }
+        }
+        path.pop();
+
+        or(conditions)
+    }
+
+    /// `child_filter` plus all rows referenced by selected rows of a child
+    fn filter(&self, table: &'a str, path: &mut Vec<&'a str>) -> Option<String> {
+        let mut conditions = Vec::new();
+        if self.children.contains(table) {
+            conditions.extend(self.child_filter(table, path));
+        }
+
+        path.push(table);
+        for fk in &self.foreign_keys {
+            if fk.referenced_table == table
+                && self.included.contains(fk.table.as_str())
+                && !path.contains(&fk.table.as_str())
+            {
+                if let Some(filter) = self.filter(&fk.table, path) {
+                    conditions.push(self.exists(
+                        &fk.table,
+                        &fk.columns,
+                        table,
+                        &fk.referenced_columns,
+                        &filter,
+                    ));
+                }
+            }
+        }
+        path.pop();
+
+        or(conditions)
+    }
+
+    /// `EXISTS` subquery selecting the rows of `inner` which match the current
+    /// row of `outer` and `filter`
+    fn exists(
+        &self,
+        inner: &str,
+        inner_columns: &[String],
+        outer: &str,
+        outer_columns: &[String],
+        filter: &str,
+    ) -> String {
+        let join = inner_columns
+            .iter()
+            .zip(outer_columns)
+            .map(|(inner_column, outer_column)| {
+                let quote = self.quote;
+                format!(
+                    "{}.{} = {}.{}",
+                    quote(inner),
+                    quote(inner_column),
+                    quote(outer),
+                    quote(outer_column)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(" AND ");
+
+        format!(
+            "EXISTS (SELECT 1 FROM {} WHERE {} AND ({}))",
+            (self.table_ref)(inner),
+            join,
+            filter
+        )
+    }
+}
+
+fn reachable<'a>(
+    start: HashSet<&'a str>,
+    neighbours: impl Fn(&str) -> Vec<&'a str>,
+) -> HashSet<&'a str> {
+    let mut reached = start.clone();
+    let mut queue: Vec<&str> = start.into_iter().collect();
+
+    while let Some(table) = queue.pop() {
+        for neighbour in neighbours(table) {
+            if reached.insert(neighbour) {
+                queue.push(neighbour);
+            }
+        }
+    }
+
+    reached
+}
+
+fn or(conditions: Vec<String>) -> Option<String> {
+    match conditions.len() {
+        0 => None,
+        1 => conditions.into_iter().next(),
+        _ => Some(format!("({})", conditions.join(") OR ("))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(name: &str, where_clause: Option<&str>) -> TableConfig {
+        TableConfig {
+            name: name.to_string(),
+            where_clause: where_clause.map(|w| w.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn fk(table: &str, column: &str, referenced_table: &str) -> ForeignKey {
+        ForeignKey {
+            name: format!("fk_{}_{}", table, referenced_table),
+            table: table.to_string(),
+            columns: vec![column.to_string()],
+            referenced_table: referenced_table.to_string(),
+            referenced_columns: vec!["id".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_subset_filters() {
+        let tables = vec![
+            table("customers", None),
+            table("orders", Some("id < 10")),
+            table("order_items", None),
+            table("logs", None),
+        ];
+        let foreign_keys = vec![
+            fk("orders", "customer_id", "customers"),
+            fk("order_items", "order_id", "orders"),
+        ];
+
+        let filters = subset_filters(
+            &tables,
+            &foreign_keys,
+            &|table| table.to_string(),
+            |name| name.to_string(),
+        );
+
+        assert_eq!("id < 10", filters["orders"]);
+        assert_eq!(
+            "EXISTS (SELECT 1 FROM orders WHERE orders.id = order_items.order_id AND (id < 10))",
+            filters["order_items"]
+        );
+        assert_eq!(
+            "EXISTS (SELECT 1 FROM orders WHERE orders.customer_id = customers.id AND (id < 10))",
+            filters["customers"]
+        );
+        assert_eq!("1=0", filters["logs"]);
+    }
+}
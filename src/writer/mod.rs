@@ -1,11 +1,783 @@
 pub mod mssql_writer;
 pub mod mysql_writer;
+pub mod table_file;
 
+use anyhow::{bail, Context};
 use async_trait::async_trait;
+use futures::stream::BoxStream;
+use futures::TryStreamExt;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::config::{Config, TableConfig};
+use crate::data_types::{BinaryEncoding, DataType};
+use crate::dates::DateOptions;
+use crate::discovery::{discover_tables, expand_columns};
+use crate::file_names;
+use crate::foreign_keys::{dependency_order, ForeignKey};
+use crate::identifiers::{column_list, validate_table, validate_unique_columns};
+use crate::manifest::{Manifest, MANIFEST_NAME};
+use crate::output::{AtomicFile, Compression, OutputFile, Pipe, Sink};
+use crate::params::bind_params;
+use crate::pipeline::ValuePipeline;
+use crate::s3::{S3Output, S3Upload};
+use crate::spatial::{feature, spatial_columns};
+use crate::sql::sql_to_string;
+use crate::subset::subset_filters;
+use crate::writer::table_file::TableFiles;
+
+/// Options of the command line controlling how the tables are written
+#[derive(Debug)]
+pub struct WriterOptions {
+    pub dir: PathBuf,
+    /// Values of the named parameters in where clauses and queries
+    pub params: HashMap<String, String>,
+    /// Write sql files in foreign key dependency order, prefixed with their position
+    pub order_by_foreign_keys: bool,
+    /// Wrap the inserts of sql files in statements disabling constraint checks
+    pub disable_constraint_checks: bool,
+    /// Encoding of inline binary values in json files
+    pub json_binary_encoding: BinaryEncoding,
+    /// Encoding of inline binary values in sql files
+    pub sql_binary_encoding: BinaryEncoding,
+    /// Format and source time zone of date and time values, unless set per column
+    pub dates: DateOptions,
+    pub json_layout: JsonLayout,
+    pub compression: Option<Compression>,
+    /// Level of the compression, its default if not set
+    pub compression_level: Option<u32>,
+    /// Start a new part file after this many rows
+    pub max_rows_per_file: Option<usize>,
+    /// Start a new part file before it gets larger than this, uncompressed
+    pub max_bytes_per_file: Option<usize>,
+    /// Write the only table to stdout or a named pipe instead of `dir`
+    pub pipe: Option<Pipe>,
+    /// Upload the files to a bucket instead of writing them to `dir`
+    pub s3: Option<Arc<S3Output>>,
+    pub existing_files: ExistingFiles,
+    /// Template of the file names of tables without `output_name`
+    pub file_name_template: String,
+    /// Count the rows of the tables in the database and fail if a different
+    /// number was written
+    pub verify: bool,
+}
+
+/// What happens to tables whose output exists from a previous run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExistingFiles {
+    Fail,
+    Overwrite,
+    Skip,
+}
+
+/// Layout of json files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsonLayout {
+    /// Array of objects keyed by column name
+    Objects,
+    /// Column names once and the rows as arrays of values, for large tables
+    Compact,
+}
+
+impl FromStr for JsonLayout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "objects" => Ok(Self::Objects),
+            "compact" => Ok(Self::Compact),
+            _ => bail!("json layout can only be objects or compact"),
+        }
+    }
+}
+
+/// Fails if the file names of two tables are the same, like with a template
+/// without `{table}`, or one is the name of the manifest
+pub fn validate_file_names(
+    outputs: &[(&TableConfig, String)],
+    manifest_name: &str,
+) -> anyhow::Result<()> {
+    let mut tables = HashMap::new();
+    for (table, file_name) in outputs {
+        if file_name == manifest_name {
+            bail!("table {} is written to {}, the name of the manifest", table.name, file_name);
+        }
+        if let Some(other) = tables.insert(file_name.as_str(), table.name.as_str()) {
+            bail!("tables {} and {} are both written to {}", other, table.name, file_name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Files below `dir` whose relative names start with `prefix`
+fn local_file_names(dir: &Path, prefix: &str) -> std::io::Result<Vec<String>> {
+    let (parent, start) = match prefix.rfind('/') {
+        Some(slash) => prefix.split_at(slash + 1),
+        None => ("", prefix),
+    };
+    let entries = match std::fs::read_dir(dir.join(parent)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with(start) {
+            continue;
+        }
+        let path = format!("{}{}", parent, name);
+        if !entry.file_type()?.is_dir() {
+            names.push(path);
+        } else if start.is_empty() || name == start {
+            names.extend(local_file_names(dir, &format!("{}/", path))?);
+        }
+    }
+
+    Ok(names)
+}
+
+/// Row as json object with the keys in the order of the columns
+pub fn row_object(columns: &[String], values: Vec<DataType>) -> IndexMap<&str, DataType> {
+    columns.iter().map(String::as_str).zip(values).collect()
+}
 
 #[async_trait]
 pub trait DatabaseWriter {
     // TODO consider using async fn traits once stable
     async fn database_to_sql(&self) -> anyhow::Result<()>;
     async fn database_to_json(&self) -> anyhow::Result<()>;
+    /// One GeoJSON `FeatureCollection` per table, see [`crate::spatial::feature`]
+    async fn database_to_geojson(&self) -> anyhow::Result<()>;
+}
+
+/// Queries of a type of database, from which the tables are written the same
+/// way for every output type
+#[async_trait]
+pub trait TableSource: Sync {
+    type Connection: Send;
+    /// Aggregate counting the rows of a query
+    const COUNT: &'static str;
+
+    fn config(&self) -> &Config;
+    fn options(&self) -> &WriterOptions;
+    fn pipeline(&self) -> &ValuePipeline;
+    /// Schema of the tables in file names, the database itself in MySQL
+    fn schema(&self) -> &str;
+
+    fn identifier(name: &str) -> String;
+    /// Reference to a table in queries
+    fn table_ref(&self, table: &str) -> String;
+    /// Placeholder of a bound parameter by its position, starting at 1
+    fn placeholder(position: usize) -> String;
+    /// Query selecting the rows of `table`
+    fn table_query(&self, table: &TableConfig, filter: &str) -> String;
+    /// Statements disabling the constraint checks of `table` before its
+    /// inserts, and enabling them afterwards
+    fn constraint_checks(&self, table: &TableConfig) -> (String, String);
+
+    /// Connection the tables are read through, in a snapshot if configured
+    async fn connect(&self) -> anyhow::Result<Self::Connection>;
+    async fn end_snapshot(&self, conn: &mut Self::Connection) -> anyhow::Result<()>;
+    async fn table_names(&self) -> anyhow::Result<Vec<String>>;
+    /// Columns of a table with their types
+    async fn table_schema(&self, table: &str) -> anyhow::Result<IndexMap<String, String>>;
+    async fn foreign_keys(&self) -> anyhow::Result<Vec<ForeignKey>>;
+    /// Number in the only row of a count query
+    async fn query_count(
+        &self,
+        conn: &mut Self::Connection,
+        sql: &str,
+        values: &[String],
+    ) -> anyhow::Result<usize>;
+    /// Runs the query of `table`
+    async fn query_rows<'c>(
+        &'c self,
+        conn: &'c mut Self::Connection,
+        table: &'c TableConfig,
+        sql: &'c str,
+        values: &'c [String],
+    ) -> anyhow::Result<TableRows<'c>>;
+}
+
+/// Result of the query of a table
+pub struct TableRows<'c> {
+    /// Names of the output columns, taken from the result set for queries
+    pub columns: Vec<String>,
+    /// SQL types of the result columns
+    pub types: Vec<String>,
+    pub rows: BoxStream<'c, anyhow::Result<Vec<DataType>>>,
+}
+
+#[async_trait]
+impl<S: TableSource> DatabaseWriter for S {
+    async fn database_to_sql(&self) -> anyhow::Result<()> {
+        write_sql(self).await
+    }
+
+    async fn database_to_json(&self) -> anyhow::Result<()> {
+        write_json(self, false).await
+    }
+
+    async fn database_to_geojson(&self) -> anyhow::Result<()> {
+        write_json(self, true).await
+    }
+}
+
+/// Writes the rows as inserts, one statement per file
+async fn write_sql<S: TableSource>(source: &S) -> anyhow::Result<()> {
+    let options = source.options();
+    let tables = resolve_tables(source).await?;
+    let database = &source.config().database.database;
+
+    let mut outputs = Vec::new();
+    for (position, table) in sql_table_order(source, &tables).await?.into_iter().enumerate() {
+        outputs.push((table, options.sql_file_name(position, table, database, source.schema())?));
+    }
+
+    let frame = |table: &TableConfig, columns: &[String]| {
+        let mut header = String::new();
+        let mut footer = ";".to_string();
+        if options.disable_constraint_checks {
+            let (disable, enable) = source.constraint_checks(table);
+            header = format!("{}\n", disable);
+            footer += &format!("\n{}", enable);
+        }
+        header += &format!(
+            "INSERT INTO {} ({}) VALUES ",
+            S::identifier(&table.name),
+            column_list(columns, S::identifier)
+        );
+        Ok((header, footer))
+    };
+    let encode_row = |_: &TableConfig, _: &[String], values: Vec<DataType>| {
+        sql_to_string(&values, options.sql_binary_encoding)
+    };
+    extract_tables(source, &tables, outputs, false, frame, encode_row).await
+}
+
+/// Writes the rows as json array, or as GeoJSON feature collection
+async fn write_json<S: TableSource>(source: &S, geojson: bool) -> anyhow::Result<()> {
+    let options = source.options();
+    let tables = resolve_tables(source).await?;
+    let database = &source.config().database.database;
+
+    let extension = if geojson { "geojson" } else { "json" };
+    let mut outputs = Vec::new();
+    for table in &tables {
+        let file_name = options.table_file_name(table, database, source.schema(), extension)?;
+        outputs.push((table, file_name));
+    }
+
+    let frame = |_: &TableConfig, columns: &[String]| options.json_frame(geojson, columns);
+    let encode_row = |table: &TableConfig, columns: &[String], values: Vec<DataType>| {
+        let values = values
+            .into_iter()
+            .map(|value| value.encode_binary(options.json_binary_encoding))
+            .collect();
+        Ok(options.json_row(geojson, table, columns, values)?)
+    };
+    extract_tables(source, &tables, outputs, true, frame, encode_row).await
+}
+
+/// Writes every table to its file in `outputs`, the rows encoded by
+/// `encode_row` between the start and end of the file from `frame`, and the
+/// manifest of the run. `empty_frame` keeps the frame of files without rows.
+async fn extract_tables<S: TableSource>(
+    source: &S,
+    tables: &[TableConfig],
+    outputs: Vec<(&TableConfig, String)>,
+    empty_frame: bool,
+    frame: impl Fn(&TableConfig, &[String]) -> anyhow::Result<(String, String)>,
+    encode_row: impl Fn(&TableConfig, &[String], Vec<DataType>) -> anyhow::Result<String>,
+) -> anyhow::Result<()> {
+    let options = source.options();
+    let pipeline = source.pipeline();
+    let filters = table_filters(source, tables).await?;
+    let manifest_name =
+        options.manifest_file_name(&source.config().database.database, source.schema())?;
+    validate_file_names(&outputs, &manifest_name)?;
+    let mut manifest = options.start_manifest(&manifest_name)?;
+
+    let mut conn = source.connect().await?;
+    for (table, file_name) in TableFiles::check_existing(options, outputs)? {
+        manifest.start_table();
+        let filter = &filters[&table.name];
+        let source_rows = count_rows(source, &mut conn, table, filter).await?;
+        let (sql, values) = bind_params(
+            &source.table_query(table, filter),
+            &options.params,
+            &source.config().database.database_type,
+            S::placeholder,
+        )?;
+        let TableRows {
+            columns,
+            types,
+            mut rows,
+        } = source.query_rows(&mut conn, table, &sql, &values).await?;
+
+        let (header, footer) = frame(table, &columns)?;
+        let mut file =
+            TableFiles::create(options, table, file_name, header, ",", footer, empty_frame)?;
+        while let Some(values) = rows.try_next().await? {
+            let (partition, values) = pipeline.apply_row(table, &columns, values)?;
+            file.write_row(&partition, &encode_row(table, &columns, values)?)?;
+        }
+        let files = file.finish()?;
+        drop(rows);
+
+        // without a snapshot the rows may have changed while they were read
+        if !source.config().database.snapshot {
+            let rows_after = count_rows(source, &mut conn, table, filter).await?;
+            if let (Some(before), Some(after)) = (source_rows, rows_after) {
+                if before != after {
+                    bail!(
+                        "rows of table {} changed while it was written, from {} to {}",
+                        table.name,
+                        before,
+                        after
+                    );
+                }
+            }
+        }
+        manifest.add_table(&table.name, &sql, &columns, &types, source_rows, files);
+        manifest.add_binary_files(&table.name, pipeline.binary_files(&table.name));
+        manifest.verify()?;
+    }
+
+    source.end_snapshot(&mut conn).await?;
+    options.write_manifest(&manifest_name, manifest)
+}
+
+/// Configured tables plus the tables matching the include patterns, with
+/// their columns validated against the schema and `*` expanded
+async fn resolve_tables<S: TableSource>(source: &S) -> anyhow::Result<Vec<TableConfig>> {
+    let config = source.config();
+    let mut tables = config.tables.clone();
+    if !config.database.include.is_empty() {
+        let table_names = source.table_names().await?;
+        tables.extend(discover_tables(config, table_names)?);
+    }
+
+    for table in &mut tables {
+        if table.query.is_none() {
+            let schema = source.table_schema(&table.name).await?;
+            validate_table(table, &schema)?;
+            expand_columns(table, &schema);
+            validate_unique_columns(table, &table.columns)?;
+            table.spatial_columns = spatial_columns(table, &schema);
+        }
+    }
+
+    source.options().validate_tables(&tables)?;
+    Ok(tables)
+}
+
+/// Tables in the order their sql files are written
+async fn sql_table_order<'b, S: TableSource>(
+    source: &S,
+    tables: &'b [TableConfig],
+) -> anyhow::Result<Vec<&'b TableConfig>> {
+    if source.options().order_by_foreign_keys {
+        let foreign_keys = source.foreign_keys().await?;
+        return Ok(dependency_order(tables, &foreign_keys));
+    }
+
+    Ok(tables.iter().collect())
+}
+
+/// Where clause of every table, following foreign keys if configured
+async fn table_filters<S: TableSource>(
+    source: &S,
+    tables: &[TableConfig],
+) -> anyhow::Result<HashMap<String, String>> {
+    if source.config().follow_foreign_keys {
+        let foreign_keys = source.foreign_keys().await?;
+        return Ok(subset_filters(
+            tables,
+            &foreign_keys,
+            &|table| source.table_ref(table),
+            S::identifier,
+        ));
+    }
+
+    Ok(tables
+        .iter()
+        .map(|table| {
+            let filter = table.where_clause.clone().unwrap_or_else(|| "1=1".to_string());
+            (table.name.clone(), filter)
+        })
+        .collect())
+}
+
+/// Rows of `table` in the database with its filter, or of its query, if
+/// they are verified
+async fn count_rows<S: TableSource>(
+    source: &S,
+    conn: &mut S::Connection,
+    table: &TableConfig,
+    filter: &str,
+) -> anyhow::Result<Option<usize>> {
+    let options = source.options();
+    if !options.verify {
+        return Ok(None);
+    }
+
+    let sql = match &table.query {
+        Some(query) => format!("SELECT {} FROM ({}) q", S::COUNT, query),
+        None => format!(
+            "SELECT {} FROM {} WHERE {}",
+            S::COUNT,
+            source.table_ref(&table.name),
+            filter
+        ),
+    };
+    let (sql, values) = bind_params(
+        &sql,
+        &options.params,
+        &source.config().database.database_type,
+        S::placeholder,
+    )?;
+
+    Ok(Some(source.query_count(conn, &sql, &values).await?))
+}
+
+impl WriterOptions {
+    /// Creates a file in the output directory, with the extension of the
+    /// compression appended to its name. The name may contain directories.
+    pub fn create_file(&self, file_name: &str) -> anyhow::Result<OutputFile> {
+        let sink = self.create_sink(&self.output_name(file_name))?;
+        Ok(OutputFile::new(sink, self.compression, self.compression_level)?)
+    }
+
+    /// Manifest the run starts with. An existing manifest is an error unless
+    /// existing files are overwritten, or skipped, when the tables of the
+    /// earlier run are kept.
+    pub fn start_manifest(&self, name: &str) -> anyhow::Result<Manifest> {
+        if self.pipe.is_some() || !self.output_exists(name, false)? {
+            return Ok(Manifest::start());
+        }
+
+        match self.existing_files {
+            ExistingFiles::Overwrite => Ok(Manifest::start()),
+            ExistingFiles::Skip => {
+                let earlier = serde_json::from_str(&self.read_output(name)?)
+                    .with_context(|| format!("{} of an earlier run is no manifest", name))?;
+                Ok(Manifest::continue_from(earlier))
+            }
+            ExistingFiles::Fail => bail!(
+                "manifest {} of an earlier run exists, use --overwrite or --skip-existing",
+                name
+            ),
+        }
+    }
+
+    /// Writes the manifest uncompressed next to the files, unless they go to
+    /// a pipe
+    pub fn write_manifest(&self, name: &str, manifest: Manifest) -> anyhow::Result<()> {
+        if self.pipe.is_some() {
+            return Ok(());
+        }
+
+        let mut file = OutputFile::new(self.create_sink(name)?, None, None)?;
+        serde_json::to_writer_pretty(&mut file, &manifest.finish())?;
+        file.finish()?;
+
+        Ok(())
+    }
+
+    /// Pipe, object in the bucket, or file in the output directory
+    fn create_sink(&self, file_name: &str) -> anyhow::Result<Sink> {
+        match (&self.pipe, &self.s3) {
+            (Some(Pipe::Stdout), _) => Ok(Sink::Stdout(std::io::stdout())),
+            (Some(Pipe::Fifo(path)), _) => {
+                Ok(Sink::File(OpenOptions::new().write(true).open(path)?))
+            }
+            (None, Some(s3)) => Ok(Sink::S3(S3Upload::new(s3.clone(), s3.key(file_name)))),
+            (None, None) => {
+                let path = self.dir.join(file_name);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let file = AtomicFile::create(&path).map_err(|err| {
+                    anyhow::anyhow!("unable to create {}: {}", path.display(), err)
+                })?;
+                Ok(Sink::Atomic(file))
+            }
+        }
+    }
+
+    /// Name of a file with the extension of the compression
+    pub fn output_name(&self, file_name: &str) -> String {
+        match self.compression {
+            Some(compression) => format!("{}.{}", file_name, compression.extension()),
+            None => file_name.to_string(),
+        }
+    }
+
+    /// Whether a file or a directory exists in the output directory or bucket.
+    /// Requests to S3 block, so the runtime moves its other tasks meanwhile.
+    pub fn output_exists(&self, name: &str, is_dir: bool) -> anyhow::Result<bool> {
+        match &self.s3 {
+            Some(s3) if is_dir => tokio::task::block_in_place(|| {
+                s3.prefix_exists(&format!("{}/", s3.key(name)))
+            }),
+            Some(s3) => tokio::task::block_in_place(|| s3.exists(&s3.key(name))),
+            None => Ok(self.dir.join(name).exists()),
+        }
+    }
+
+    /// Text of a file in the output directory or bucket
+    fn read_output(&self, name: &str) -> anyhow::Result<String> {
+        match &self.s3 {
+            Some(s3) => tokio::task::block_in_place(|| s3.get(&s3.key(name))),
+            None => Ok(std::fs::read_to_string(self.dir.join(name))?),
+        }
+    }
+
+    /// Files in the output directory or bucket whose names start with
+    /// `prefix`, relative to the directory or the prefix of the bucket
+    pub fn output_names(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        match &self.s3 {
+            Some(s3) => {
+                let bucket_prefix = s3.key("");
+                let keys = tokio::task::block_in_place(|| s3.list_keys(&s3.key(prefix)))?;
+                Ok(keys
+                    .into_iter()
+                    .filter_map(|key| key.strip_prefix(&bucket_prefix).map(str::to_string))
+                    .collect())
+            }
+            None => Ok(local_file_names(&self.dir, prefix)?),
+        }
+    }
+
+    /// Removes a file of an earlier run, and its directories if they are
+    /// empty then
+    pub fn remove_output(&self, name: &str) -> anyhow::Result<()> {
+        match &self.s3 {
+            Some(s3) => tokio::task::block_in_place(|| s3.delete(&s3.key(name))),
+            None => {
+                std::fs::remove_file(self.dir.join(name))?;
+                let mut parent = Path::new(name).parent();
+                while let Some(dir) = parent.filter(|dir| !dir.as_os_str().is_empty()) {
+                    if std::fs::remove_dir(self.dir.join(dir)).is_err() {
+                        break;
+                    }
+                    parent = dir.parent();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// A pipe takes the output of a single table in a single file, and binary
+    /// files are only written to local directories
+    pub fn validate_tables(&self, tables: &[TableConfig]) -> anyhow::Result<()> {
+        if self.s3.is_some() {
+            if let Some(table) = tables.iter().find(|table| table.binary_files) {
+                bail!("binary files of table {} can not be written to S3", table.name);
+            }
+        }
+        if self.pipe.is_none() {
+            return Ok(());
+        }
+
+        if tables.len() != 1 {
+            bail!("only one table can be written to a pipe, but there are {}", tables.len());
+        }
+        let table = &tables[0];
+        if !table.partition_by.is_empty() || table.binary_files {
+            bail!("table {} is written to several files and not to a pipe", table.name);
+        }
+
+        Ok(())
+    }
+
+    /// Start and end of a json file around its comma separated rows
+    pub fn json_frame(
+        &self,
+        geojson: bool,
+        columns: &[String],
+    ) -> anyhow::Result<(String, String)> {
+        if geojson {
+            Ok((r#"{"type":"FeatureCollection","features":["#.to_string(), "]}".to_string()))
+        } else if self.json_layout == JsonLayout::Compact {
+            let header = format!(r#"{{"columns":{},"rows":["#, serde_json::to_string(columns)?);
+            Ok((header, "]}".to_string()))
+        } else {
+            Ok(("[".to_string(), "]".to_string()))
+        }
+    }
+
+    /// Row of a json file, as object, array of values or GeoJSON feature
+    pub fn json_row(
+        &self,
+        geojson: bool,
+        table: &TableConfig,
+        columns: &[String],
+        values: Vec<DataType>,
+    ) -> serde_json::Result<String> {
+        if geojson {
+            serde_json::to_string(&feature(table, row_object(columns, values)))
+        } else if self.json_layout == JsonLayout::Compact {
+            serde_json::to_string(&values)
+        } else {
+            serde_json::to_string(&row_object(columns, values))
+        }
+    }
+
+    /// Name of the file of `table` with the extension `ext`, from its
+    /// `output_name` or the file name template
+    pub fn table_file_name(
+        &self,
+        table: &TableConfig,
+        database: &str,
+        schema: &str,
+        ext: &str,
+    ) -> anyhow::Result<String> {
+        let template = table.output_name.as_ref().unwrap_or(&self.file_name_template);
+        self.render_file_name(template, &table.name, database, schema, ext)
+    }
+
+    /// Name of the manifest from the file name template, with `manifest` as
+    /// table, so it is written next to the files of the run
+    pub fn manifest_file_name(&self, database: &str, schema: &str) -> anyhow::Result<String> {
+        self.render_file_name(&self.file_name_template, MANIFEST_NAME, database, schema, "json")
+    }
+
+    fn render_file_name(
+        &self,
+        template: &str,
+        table: &str,
+        database: &str,
+        schema: &str,
+        ext: &str,
+    ) -> anyhow::Result<String> {
+        let param = |name: &str| self.params.get(name).map(String::as_str).unwrap_or_default();
+        let values = vec![
+            ("table", table),
+            ("schema", schema),
+            ("database", database),
+            ("date", param("run_date")),
+            ("run_id", param("run_id")),
+            ("ext", ext),
+        ];
+
+        file_names::render(template, &values.into_iter().collect())
+    }
+
+    /// Name of the sql file of `table`, prefixed with its position if the
+    /// files are ordered by foreign keys
+    pub fn sql_file_name(
+        &self,
+        position: usize,
+        table: &TableConfig,
+        database: &str,
+        schema: &str,
+    ) -> anyhow::Result<String> {
+        let file_name = self.table_file_name(table, database, schema, "sql")?;
+        if !self.order_by_foreign_keys {
+            return Ok(file_name);
+        }
+
+        let (dir, name) = match file_name.rfind('/') {
+            Some(slash) => file_name.split_at(slash + 1),
+            None => ("", file_name.as_str()),
+        };
+        Ok(format!("{}{:03}_{}", dir, position + 1, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dates::DateFormat;
+    use crate::file_names::DEFAULT_TEMPLATE;
+
+    pub fn options(
+        dir: &Path,
+        max_rows_per_file: Option<usize>,
+        existing_files: ExistingFiles,
+    ) -> WriterOptions {
+        WriterOptions {
+            dir: dir.to_path_buf(),
+            params: HashMap::new(),
+            order_by_foreign_keys: false,
+            disable_constraint_checks: false,
+            json_binary_encoding: BinaryEncoding::Base64,
+            sql_binary_encoding: BinaryEncoding::Literal,
+            dates: DateOptions {
+                format: DateFormat::Text,
+                source_timezone: None,
+            },
+            json_layout: JsonLayout::Objects,
+            compression: None,
+            compression_level: None,
+            max_rows_per_file,
+            max_bytes_per_file: None,
+            pipe: None,
+            s3: None,
+            existing_files,
+            file_name_template: DEFAULT_TEMPLATE.to_string(),
+            verify: false,
+        }
+    }
+
+    #[test]
+    fn test_row_object_keeps_column_order() {
+        let columns = vec!["zip".to_string(), "city".to_string(), "area".to_string()];
+        let values = vec![
+            DataType::String(Some("10115".to_string())),
+            DataType::String(Some("Berlin".to_string())),
+            DataType::Int(None),
+        ];
+
+        assert_eq!(
+            serde_json::to_string(&row_object(&columns, values)).unwrap(),
+            r#"{"zip":"10115","city":"Berlin","area":null}"#
+        );
+    }
+
+    #[test]
+    fn test_tables_need_their_own_files() {
+        let table = |name: &str| TableConfig {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let (orders, customers) = (table("orders"), table("customers"));
+
+        let validate = |orders_file: &str, customers_file: &str| {
+            let outputs = [
+                (&orders, orders_file.to_string()),
+                (&customers, customers_file.to_string()),
+            ];
+            validate_file_names(&outputs, "manifest.json")
+        };
+        assert!(validate("orders.json", "customers.json").is_ok());
+        assert!(validate("2021-01-01.json", "2021-01-01.json").is_err());
+        assert!(validate("orders.json", "manifest.json").is_err());
+    }
+
+    #[test]
+    fn test_pipes_take_a_single_table_in_a_single_file() {
+        let table = |name: &str, binary_files| TableConfig {
+            name: name.to_string(),
+            binary_files,
+            ..Default::default()
+        };
+        let mut options = options(Path::new("-"), None, ExistingFiles::Overwrite);
+        assert!(options.validate_tables(&[table("orders", false), table("items", false)]).is_ok());
+
+        options.pipe = Some(Pipe::Stdout);
+        assert!(options.validate_tables(&[table("orders", false)]).is_ok());
+        assert!(options.validate_tables(&[table("orders", false), table("items", false)]).is_err());
+        assert!(options.validate_tables(&[table("orders", true)]).is_err());
+    }
 }
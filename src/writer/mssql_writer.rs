@@ -1,157 +1,164 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use anyhow::bail;
+use async_trait::async_trait;
+use futures::{future, StreamExt, TryStreamExt};
+use indexmap::IndexMap;
+use tiberius::xml::XmlData;
 use tiberius::Client;
-use tiberius::Row;
+use tiberius::{Column, ColumnType, Row, ToSql};
 use tokio::net::TcpStream;
 use tokio_util::compat::{Compat, TokioAsyncWriteCompatExt};
 
-use crate::config::Config;
+use crate::config::{Config, TableConfig};
 use crate::data_types::DataType;
-use crate::sql::sql_to_string;
-use crate::DatabaseWriter;
+use crate::foreign_keys::{group_foreign_keys, ForeignKey, ForeignKeyColumn};
+use crate::identifiers::{mssql_identifier, validate_unique_columns};
+use crate::pipeline::ValuePipeline;
+use crate::spatial::select_list;
+use crate::writer::{TableRows, TableSource, WriterOptions};
 
 pub struct MssqlWriter<'a> {
     config: &'a Config,
-    dir: PathBuf,
+    options: WriterOptions,
+    pipeline: ValuePipeline,
 }
 
-use async_trait::async_trait;
+impl<'a> MssqlWriter<'a> {
+    pub async fn new(
+        config: &'a Config,
+        options: WriterOptions,
+    ) -> anyhow::Result<MssqlWriter<'a>> {
+        let pipeline = ValuePipeline::new(config, &options)?;
+        Ok(MssqlWriter {
+            config,
+            options,
+            pipeline,
+        })
+    }
+
+    async fn new_client(&self) -> anyhow::Result<Client<Compat<TcpStream>>> {
+        let db_config = self.config.mssql_config();
+
+        let tcp = TcpStream::connect(db_config.get_addr()).await?;
+        tcp.set_nodelay(true)?;
+
+        Ok(Client::connect(db_config, tcp.compat_write()).await?)
+    }
+}
 
 #[async_trait]
-impl<'a> DatabaseWriter for MssqlWriter<'a> {
-    async fn database_to_sql(&self) -> anyhow::Result<()> {
-        self.database_to_sql_().await
+impl<'a> TableSource for MssqlWriter<'a> {
+    type Connection = Client<Compat<TcpStream>>;
+
+    const COUNT: &'static str = "COUNT_BIG(*)";
+
+    fn config(&self) -> &Config {
+        self.config
     }
 
-    async fn database_to_json(&self) -> anyhow::Result<()> {
-        self.database_to_json_().await
+    fn options(&self) -> &WriterOptions {
+        &self.options
     }
-}
 
-impl<'a> MssqlWriter<'a> {
-    pub async fn new(config: &'a Config, dir: PathBuf) -> anyhow::Result<MssqlWriter<'a>> {
-        Ok(MssqlWriter { config, dir })
+    fn pipeline(&self) -> &ValuePipeline {
+        &self.pipeline
     }
 
-    async fn database_to_sql_(&self) -> anyhow::Result<()> {
-        let mut client = self.new_client().await?;
+    fn schema(&self) -> &str {
+        "dbo"
+    }
 
-        for table in &self.config.tables {
-            let schema = self.get_schema_for_table(&table.name).await?;
-
-            let table_str = table.columns.join(",");
-            let sql = format!(
-                "select {} from {}.dbo.{} where {}",
-                table_str,
-                self.config.database.database,
-                table.name,
-                table.where_clause.as_ref().unwrap_or(&"1=1".to_string())
-            );
-
-            let stream = client.query(sql, &[]).await?;
-
-            let file = File::create(self.dir.join(format!("{}.sql", table.name)))
-                .expect("Unable to create file");
-            let mut file = BufWriter::new(file);
-            file.write_all(
-                format!("INSERT INTO {} ({}) VALUES ", &table.name, table_str).as_bytes(),
-            )
-            .expect("Unable to write data");
-
-            let rows = stream.into_first_result().await?;
-            let mut initial = true;
-
-            for row in rows {
-                if !initial {
-                    file.write_all(",".as_bytes())
-                        .expect("Unable to write data");
-                } else {
-                    initial = false;
-                }
-
-                let mut values = Vec::new();
-                for (idx, column) in table.columns.iter().enumerate() {
-                    values.push(mssql_value(schema.get(column).unwrap(), &row, idx)?);
-                }
-                file.write_all(sql_to_string(&values).unwrap().as_bytes())
-                    .expect("Unable to write data");
-            }
+    fn identifier(name: &str) -> String {
+        mssql_identifier(name)
+    }
+
+    fn table_ref(&self, table: &str) -> String {
+        format!(
+            "{}.dbo.{}",
+            mssql_identifier(&self.config.database.database),
+            mssql_identifier(table)
+        )
+    }
+
+    fn placeholder(position: usize) -> String {
+        format!("@P{}", position)
+    }
 
-            file.write_all(";".as_bytes())
-                .expect("Unable to write data");
-            file.flush()?;
+    fn table_query(&self, table: &TableConfig, filter: &str) -> String {
+        match &table.query {
+            Some(query) => query.clone(),
+            None => format!(
+                "select {} from {} where {}",
+                select_list(table, mssql_identifier, |column| format!("{}.STAsText()", column)),
+                self.table_ref(&table.name),
+                filter
+            ),
         }
+    }
 
-        Ok(())
+    fn constraint_checks(&self, table: &TableConfig) -> (String, String) {
+        let table = mssql_identifier(&table.name);
+        // without WITH CHECK the rows are not validated again, as their
+        // parents may only be loaded later in the run
+        (
+            format!("ALTER TABLE {} NOCHECK CONSTRAINT ALL;", table),
+            format!("ALTER TABLE {} CHECK CONSTRAINT ALL;", table),
+        )
     }
 
-    async fn database_to_json_(&self) -> anyhow::Result<()> {
+    /// Opens a `SNAPSHOT` transaction on the client if configured, so every
+    /// table queried through it sees the same version of the database.
+    async fn connect(&self) -> anyhow::Result<Client<Compat<TcpStream>>> {
         let mut client = self.new_client().await?;
+        if self.config.database.snapshot {
+            client
+                .simple_query("SET TRANSACTION ISOLATION LEVEL SNAPSHOT; BEGIN TRANSACTION")
+                .await?
+                .into_results()
+                .await?;
+        }
 
-        for table in &self.config.tables {
-            let schema = self.get_schema_for_table(&table.name).await?;
-
-            let table_str = table.columns.join(",");
-            let sql = format!(
-                "select {} from {}.dbo.{} where {}",
-                table_str,
-                self.config.database.database,
-                table.name,
-                table.where_clause.as_ref().unwrap_or(&"1=1".to_string())
-            );
-
-            let stream = client.query(sql, &[]).await?;
-
-            let file = File::create(self.dir.join(format!("{}.json", table.name)))
-                .expect("Unable to create file");
-            let mut file = BufWriter::new(file);
-
-            let rows = stream.into_first_result().await?;
-            let rows: Vec<HashMap<&str, DataType>> = rows
-                .into_iter()
-                .map(|row| {
-                    let mut key_value_map = HashMap::new();
-                    for (idx, column) in table.columns.iter().enumerate() {
-                        key_value_map.insert(
-                            (*column).as_str(),
-                            mssql_value(schema.get(column).unwrap(), &row, idx).unwrap(),
-                        );
-                    }
-                    key_value_map
-                })
-                .collect();
-
-            file.write_all(serde_json::to_string_pretty(&rows).unwrap().as_bytes())?;
-            file.flush()?;
+        Ok(client)
+    }
+
+    async fn end_snapshot(&self, client: &mut Client<Compat<TcpStream>>) -> anyhow::Result<()> {
+        if self.config.database.snapshot {
+            client.simple_query("COMMIT").await?.into_results().await?;
         }
 
         Ok(())
     }
 
-    async fn new_client(&self) -> anyhow::Result<Client<Compat<TcpStream>>> {
-        let db_config = self.config.mssql_config();
+    async fn table_names(&self) -> anyhow::Result<Vec<String>> {
+        let sql = format!(
+            "SELECT TABLE_NAME FROM {}.INFORMATION_SCHEMA.TABLES \
+             WHERE TABLE_SCHEMA = 'dbo' AND TABLE_TYPE = 'BASE TABLE' ORDER BY TABLE_NAME",
+            mssql_identifier(&self.config.database.database)
+        );
+        let mut client = self.new_client().await?;
 
-        let tcp = TcpStream::connect(db_config.get_addr()).await?;
-        tcp.set_nodelay(true)?;
+        let stream = client.query(sql, &[]).await?;
 
-        Ok(Client::connect(db_config, tcp.compat_write()).await?)
+        let mut table_names = Vec::new();
+        let rows = stream.into_first_result().await?;
+        for row in rows {
+            let table_name: &str = row.try_get(0)?.unwrap();
+            table_names.push(table_name.to_string());
+        }
+
+        Ok(table_names)
     }
 
-    async fn get_schema_for_table<'b>(
-        &self,
-        table_name: &'b str,
-    ) -> anyhow::Result<HashMap<String, String>> {
+    async fn table_schema(&self, table: &str) -> anyhow::Result<IndexMap<String, String>> {
         let sql = format!(
-            "SELECT COLUMN_NAME, DATA_TYPE FROM {}.INFORMATION_SCHEMA.COLUMNS WHERE TABLE_NAME=@P1",
-            self.config.database.database
+            "SELECT COLUMN_NAME, DATA_TYPE FROM {}.INFORMATION_SCHEMA.COLUMNS WHERE TABLE_NAME=@P1 \
+             ORDER BY ORDINAL_POSITION",
+            mssql_identifier(&self.config.database.database)
         );
         let mut client = self.new_client().await?;
 
-        let stream = client.query(sql, &[&table_name.to_string()]).await?;
+        let stream = client.query(sql, &[&table.to_string()]).await?;
 
-        let mut schema = HashMap::new();
+        let mut schema = IndexMap::new();
         let rows = stream.into_first_result().await?;
         for row in rows {
             let column_name: &str = row.try_get(0)?.unwrap();
@@ -161,25 +168,224 @@ impl<'a> MssqlWriter<'a> {
 
         Ok(schema)
     }
+
+    async fn foreign_keys(&self) -> anyhow::Result<Vec<ForeignKey>> {
+        let sql = format!(
+            "SELECT rc.CONSTRAINT_NAME, fk.TABLE_NAME, fk.COLUMN_NAME, pk.TABLE_NAME, pk.COLUMN_NAME \
+             FROM {0}.INFORMATION_SCHEMA.REFERENTIAL_CONSTRAINTS rc \
+             JOIN {0}.INFORMATION_SCHEMA.KEY_COLUMN_USAGE fk \
+             ON fk.CONSTRAINT_SCHEMA = rc.CONSTRAINT_SCHEMA AND fk.CONSTRAINT_NAME = rc.CONSTRAINT_NAME \
+             JOIN {0}.INFORMATION_SCHEMA.KEY_COLUMN_USAGE pk \
+             ON pk.CONSTRAINT_SCHEMA = rc.UNIQUE_CONSTRAINT_SCHEMA \
+             AND pk.CONSTRAINT_NAME = rc.UNIQUE_CONSTRAINT_NAME \
+             AND pk.ORDINAL_POSITION = fk.ORDINAL_POSITION \
+             ORDER BY rc.CONSTRAINT_NAME, fk.TABLE_NAME, fk.ORDINAL_POSITION",
+            mssql_identifier(&self.config.database.database)
+        );
+        let mut client = self.new_client().await?;
+
+        let stream = client.query(sql, &[]).await?;
+
+        let mut columns = Vec::new();
+        let rows = stream.into_first_result().await?;
+        for row in rows {
+            let get = |idx: usize| -> anyhow::Result<String> {
+                let value: &str = row.try_get(idx)?.unwrap();
+                Ok(value.to_string())
+            };
+            columns.push(ForeignKeyColumn {
+                constraint_name: get(0)?,
+                table: get(1)?,
+                column: get(2)?,
+                referenced_table: get(3)?,
+                referenced_column: get(4)?,
+            });
+        }
+
+        Ok(group_foreign_keys(columns))
+    }
+
+    async fn query_count(
+        &self,
+        client: &mut Client<Compat<TcpStream>>,
+        sql: &str,
+        values: &[String],
+    ) -> anyhow::Result<usize> {
+        let params: Vec<&dyn ToSql> = values.iter().map(|value| value as &dyn ToSql).collect();
+        let row = client.query(sql, &params).await?.into_row().await?;
+        let count: Option<i64> = match &row {
+            Some(row) => row.try_get(0)?,
+            None => None,
+        };
+
+        Ok(count.unwrap_or_default() as usize)
+    }
+
+    async fn query_rows<'c>(
+        &'c self,
+        client: &'c mut Client<Compat<TcpStream>>,
+        table: &'c TableConfig,
+        sql: &'c str,
+        values: &'c [String],
+    ) -> anyhow::Result<TableRows<'c>> {
+        let params: Vec<&dyn ToSql> = values.iter().map(|value| value as &dyn ToSql).collect();
+        let mut stream = client.query(sql, &params).await?;
+        let result_columns = stream.columns().await?;
+        let types = column_types(result_columns);
+        let columns = output_columns(table, result_columns)?;
+
+        let count = columns.len();
+        let rows = stream
+            .into_row_stream()
+            // only the first result set, like `into_first_result`
+            .try_filter(|row| future::ready(row.result_index() == 0))
+            .map_err(anyhow::Error::from)
+            .and_then(move |row| {
+                future::ready((0..count).map(|idx| mssql_value(&row, idx)).collect())
+            })
+            .boxed();
+        Ok(TableRows {
+            columns,
+            types,
+            rows,
+        })
+    }
+}
+
+/// Column names of the output, taken from the result set for queries
+fn output_columns(
+    table: &TableConfig,
+    result_columns: Option<&[Column]>,
+) -> anyhow::Result<Vec<String>> {
+    match (&table.query, result_columns) {
+        (Some(_), Some(columns)) => {
+            let columns: Vec<String> =
+                columns.iter().map(|column| column.name().to_string()).collect();
+            validate_unique_columns(table, &columns)?;
+            Ok(columns)
+        }
+        _ => Ok(table.columns.clone()),
+    }
+}
+
+fn column_types(result_columns: Option<&[Column]>) -> Vec<String> {
+    result_columns
+        .unwrap_or_default()
+        .iter()
+        .map(|column| type_name(column.column_type()).to_string())
+        .collect()
 }
 
-fn mssql_value(data_type: &str, row: &Row, column_idx: usize) -> anyhow::Result<DataType> {
-    match data_type {
-        "varchar" | "nvarchar" => {
+/// Name of a column type in SQL. Nullable columns only tell their kind, so
+/// for example `Intn` can be any of the integer types and is named `INT`.
+fn type_name(column_type: ColumnType) -> &'static str {
+    match column_type {
+        ColumnType::Null => "NULL",
+        ColumnType::Bit | ColumnType::Bitn => "BIT",
+        ColumnType::Int1 => "TINYINT",
+        ColumnType::Int2 => "SMALLINT",
+        ColumnType::Int4 | ColumnType::Intn => "INT",
+        ColumnType::Int8 => "BIGINT",
+        ColumnType::Datetime4 => "SMALLDATETIME",
+        ColumnType::Float4 => "REAL",
+        ColumnType::Float8 | ColumnType::Floatn => "FLOAT",
+        ColumnType::Money => "MONEY",
+        ColumnType::Money4 => "SMALLMONEY",
+        ColumnType::Datetime | ColumnType::Datetimen => "DATETIME",
+        ColumnType::Guid => "UNIQUEIDENTIFIER",
+        ColumnType::Decimaln => "DECIMAL",
+        ColumnType::Numericn => "NUMERIC",
+        ColumnType::Daten => "DATE",
+        ColumnType::Timen => "TIME",
+        ColumnType::Datetime2 => "DATETIME2",
+        ColumnType::DatetimeOffsetn => "DATETIMEOFFSET",
+        ColumnType::BigVarBin => "VARBINARY",
+        ColumnType::BigVarChar => "VARCHAR",
+        ColumnType::BigBinary => "BINARY",
+        ColumnType::BigChar => "CHAR",
+        ColumnType::NVarchar => "NVARCHAR",
+        ColumnType::NChar => "NCHAR",
+        ColumnType::Xml => "XML",
+        ColumnType::Udt => "UDT",
+        ColumnType::Text => "TEXT",
+        ColumnType::Image => "IMAGE",
+        ColumnType::NText => "NTEXT",
+        ColumnType::SSVariant => "SQL_VARIANT",
+    }
+}
+
+/// Converts a value by the column type of the result set, so the query
+/// decides the type and not the schema of the table
+fn mssql_value(row: &Row, column_idx: usize) -> anyhow::Result<DataType> {
+    let column = &row.columns()[column_idx];
+    let column_type = column.column_type();
+    match column_type {
+        // type of literal nulls like `SELECT NULL AS x`
+        ColumnType::Null => Ok(DataType::String(None)),
+        ColumnType::BigVarChar
+        | ColumnType::BigChar
+        | ColumnType::NVarchar
+        | ColumnType::NChar
+        | ColumnType::Text
+        | ColumnType::NText => {
             let t: Option<&str> = row.try_get(column_idx)?;
             Ok(DataType::String(t.map(|s| s.to_string())))
         }
-        "int" => Ok(DataType::Int(row.try_get(column_idx)?)),
-        "bigint" => Ok(DataType::BigInt(row.try_get(column_idx)?)),
-        "float" => Ok(DataType::Float(row.try_get(column_idx)?)),
-        "decimal" => Ok(DataType::Decimal(row.try_get(column_idx)?)),
-        "smallint" => Ok(DataType::Bool(row.try_get(column_idx)?)),
-        "bit" => Ok(DataType::Bool(row.try_get(column_idx)?)),
-        "uniqueidentifier" => Ok(DataType::Uuid(row.try_get(column_idx)?)),
-        "datetime" => Ok(DataType::DateTime(row.try_get(column_idx)?)),
-        "datetimeoffset" => Ok(DataType::DateTime(row.try_get(column_idx)?)),
-        "date" => Ok(DataType::Date(row.try_get(column_idx)?)),
-        "time" => Ok(DataType::Time(row.try_get(column_idx)?)),
-        _ => panic!("{} not yet implemented", data_type),
+        ColumnType::Xml => {
+            let t: Option<&XmlData> = row.try_get(column_idx)?;
+            Ok(DataType::String(t.map(|xml| xml.to_string())))
+        }
+        ColumnType::Int1 => {
+            let t: Option<u8> = row.try_get(column_idx)?;
+            Ok(DataType::Int(t.map(i32::from)))
+        }
+        ColumnType::Int2 => {
+            let t: Option<i16> = row.try_get(column_idx)?;
+            Ok(DataType::Int(t.map(i32::from)))
+        }
+        ColumnType::Int4 => Ok(DataType::Int(row.try_get(column_idx)?)),
+        ColumnType::Int8 => Ok(DataType::BigInt(row.try_get(column_idx)?)),
+        // the width of nullable integers is only known from the value itself
+        ColumnType::Intn => {
+            if let Ok(t) = row.try_get::<i32, _>(column_idx) {
+                Ok(DataType::Int(t))
+            } else if let Ok(t) = row.try_get::<i64, _>(column_idx) {
+                Ok(DataType::BigInt(t))
+            } else if let Ok(t) = row.try_get::<i16, _>(column_idx) {
+                Ok(DataType::Int(t.map(i32::from)))
+            } else {
+                let t: Option<u8> = row.try_get(column_idx)?;
+                Ok(DataType::Int(t.map(i32::from)))
+            }
+        }
+        ColumnType::Float4 => Ok(DataType::Float(row.try_get(column_idx)?)),
+        ColumnType::Float8 | ColumnType::Money | ColumnType::Money4 => {
+            Ok(DataType::Double(row.try_get(column_idx)?))
+        }
+        ColumnType::Floatn => match row.try_get::<f64, _>(column_idx) {
+            Ok(t) => Ok(DataType::Double(t)),
+            Err(_) => Ok(DataType::Float(row.try_get(column_idx)?)),
+        },
+        ColumnType::Decimaln | ColumnType::Numericn => {
+            Ok(DataType::Decimal(row.try_get(column_idx)?))
+        }
+        ColumnType::Bit | ColumnType::Bitn => Ok(DataType::Bool(row.try_get(column_idx)?)),
+        ColumnType::Guid => Ok(DataType::Uuid(row.try_get(column_idx)?)),
+        ColumnType::Datetime
+        | ColumnType::Datetime4
+        | ColumnType::Datetimen
+        | ColumnType::Datetime2 => Ok(DataType::DateTime(row.try_get(column_idx)?)),
+        ColumnType::DatetimeOffsetn => Ok(DataType::DateTimeUtc(row.try_get(column_idx)?)),
+        ColumnType::Daten => Ok(DataType::Date(row.try_get(column_idx)?)),
+        ColumnType::Timen => Ok(DataType::Time(row.try_get(column_idx)?)),
+        ColumnType::BigVarBin | ColumnType::BigBinary | ColumnType::Image => {
+            let t: Option<&[u8]> = row.try_get(column_idx)?;
+            Ok(DataType::Binary(t.map(|bytes| bytes.to_vec())))
+        }
+        _ => bail!(
+            "column {} has the type {}, which is not supported",
+            column.name(),
+            type_name(column_type)
+        ),
     }
 }
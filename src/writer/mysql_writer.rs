@@ -1,141 +1,175 @@
-use futures::TryStreamExt;
-use regex::Regex;
+use anyhow::bail;
+use async_trait::async_trait;
+use futures::{future, StreamExt, TryStreamExt};
+use indexmap::IndexMap;
 use sqlx::mysql::{MySqlPoolOptions, MySqlRow};
-use sqlx::Row;
+use sqlx::pool::PoolConnection;
+use sqlx::{Column, Executor, Row, Statement, TypeInfo};
 use sqlx::{MySql, Pool};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufWriter, Write};
-use std::path::PathBuf;
 
-use crate::config::Config;
+use crate::config::{Config, TableConfig};
 use crate::data_types::DataType;
-use crate::sql::sql_to_string;
-use crate::DatabaseWriter;
+use crate::foreign_keys::{group_foreign_keys, ForeignKey, ForeignKeyColumn};
+use crate::identifiers::{mysql_identifier, validate_unique_columns};
+use crate::pipeline::ValuePipeline;
+use crate::spatial::select_list;
+use crate::writer::{TableRows, TableSource, WriterOptions};
 
 pub struct MySqlWriter<'a> {
     config: &'a Config,
     pools: Pool<MySql>,
-    dir: PathBuf,
+    options: WriterOptions,
+    pipeline: ValuePipeline,
+    axis_order: bool,
 }
 
-use async_trait::async_trait;
+impl<'a> MySqlWriter<'a> {
+    pub async fn new(
+        config: &'a Config,
+        options: WriterOptions,
+    ) -> anyhow::Result<MySqlWriter<'a>> {
+        let pools = get_connection_pool(config).await?;
+        let pipeline = ValuePipeline::new(config, &options)?;
+        let version: String = sqlx::query_scalar("SELECT VERSION()").fetch_one(&pools).await?;
+        Ok(MySqlWriter {
+            config,
+            options,
+            pools,
+            pipeline,
+            axis_order: has_axis_order(&version),
+        })
+    }
+
+    /// Column names and types of the output, the names taken from the result
+    /// set for queries
+    async fn output_columns(
+        &self,
+        conn: &mut PoolConnection<MySql>,
+        table: &TableConfig,
+        sql: &str,
+    ) -> anyhow::Result<(Vec<String>, Vec<String>)> {
+        let statement = conn.prepare(sql).await?;
+        let types = statement
+            .columns()
+            .iter()
+            .map(|column| column.type_info().name().to_string())
+            .collect();
+        if table.query.is_none() {
+            return Ok((table.columns.clone(), types));
+        }
+
+        let columns: Vec<String> = statement
+            .columns()
+            .iter()
+            .map(|column| column.name().to_string())
+            .collect();
+        validate_unique_columns(table, &columns)?;
+        Ok((columns, types))
+    }
+}
 
 #[async_trait]
-impl<'a> DatabaseWriter for MySqlWriter<'a> {
-    async fn database_to_sql(&self) -> anyhow::Result<()> {
-        self.database_to_sql_().await.map_err(anyhow::Error::from)
+impl<'a> TableSource for MySqlWriter<'a> {
+    type Connection = PoolConnection<MySql>;
+
+    const COUNT: &'static str = "COUNT(*)";
+
+    fn config(&self) -> &Config {
+        self.config
     }
 
-    async fn database_to_json(&self) -> anyhow::Result<()> {
-        self.database_to_json_().await.map_err(anyhow::Error::from)
+    fn options(&self) -> &WriterOptions {
+        &self.options
     }
-}
 
-impl<'a> MySqlWriter<'a> {
-    pub async fn new(config: &'a Config, dir: PathBuf) -> anyhow::Result<MySqlWriter<'a>> {
-        let pools = get_connection_pool(config).await?;
-        Ok(MySqlWriter { config, dir, pools })
+    fn pipeline(&self) -> &ValuePipeline {
+        &self.pipeline
     }
 
-    async fn database_to_sql_(&self) -> Result<(), sqlx::Error> {
-        for table in &self.config.tables {
-            let schema = self.get_schema_for_table(&table.name).await?;
+    fn schema(&self) -> &str {
+        &self.config.database.database
+    }
+
+    fn identifier(name: &str) -> String {
+        mysql_identifier(name)
+    }
+
+    fn table_ref(&self, table: &str) -> String {
+        mysql_identifier(table)
+    }
+
+    fn placeholder(_: usize) -> String {
+        "?".to_string()
+    }
 
-            let table_str = table.columns.join(",");
-            let sql = format!(
+    fn table_query(&self, table: &TableConfig, filter: &str) -> String {
+        match &table.query {
+            Some(query) => query.clone(),
+            None => format!(
                 "select {} from {} where {}",
-                table_str,
-                table.name,
-                table.where_clause.as_ref().unwrap_or(&"1=1".to_string())
-            );
-            let mut rows = sqlx::query(&sql).fetch(&self.pools);
-
-            let file = File::create(self.dir.join(format!("{}.sql", table.name)))
-                .expect("Unable to create file");
-            let mut file = BufWriter::new(file);
-            file.write_all(
-                format!("INSERT INTO {} ({}) VALUES ", &table.name, table_str).as_bytes(),
-            )
-            .expect("Unable to write data");
-
-            let mut initial = true;
-
-            while let Some(row) = rows.try_next().await? {
-                if !initial {
-                    file.write_all(",".as_bytes()).expect("Unable to write data");
-                } else {
-                    initial = false;
-                }
-
-                let mut values = Vec::new();
-                for column in &table.columns {
-                    values.push(mysql_value(schema.get(column).unwrap(), &row, column)?);
-                }
-                file.write_all(sql_to_string(&values).unwrap().as_bytes())
-                    .expect("Unable to write data");
-            }
-
-            file.write_all(";".as_bytes()).expect("Unable to write data");
-            file.flush()?;
+                // Longitude first, as in WKT, also for geographic systems like SRID 4326
+                select_list(
+                    table,
+                    mysql_identifier,
+                    if self.axis_order {
+                        |column| format!("ST_AsText({}, 'axis-order=long-lat')", column)
+                    } else {
+                        |column| format!("ST_AsText({})", column)
+                    }
+                ),
+                mysql_identifier(&table.name),
+                filter
+            ),
         }
+    }
 
-        Ok(())
+    fn constraint_checks(&self, _: &TableConfig) -> (String, String) {
+        (
+            "SET FOREIGN_KEY_CHECKS=0;".to_string(),
+            "SET FOREIGN_KEY_CHECKS=1;".to_string(),
+        )
     }
 
-    async fn database_to_json_(&self) -> Result<(), sqlx::Error> {
-        for table in &self.config.tables {
-            let schema = self.get_schema_for_table(&table.name).await?;
+    /// Starts a consistent snapshot on the connection if configured, so every
+    /// table queried through it sees the same version of the database.
+    async fn connect(&self) -> anyhow::Result<PoolConnection<MySql>> {
+        let mut conn = self.pools.acquire().await?;
+        if self.config.database.snapshot {
+            conn.execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ").await?;
+            conn.execute("START TRANSACTION WITH CONSISTENT SNAPSHOT").await?;
+        }
 
-            let table_str = table.columns.join(",");
-            let sql = format!(
-                "select {} from {} where {}",
-                table_str,
-                table.name,
-                table.where_clause.as_ref().unwrap_or(&"1=1".to_string())
-            );
-            let mut rows = sqlx::query(&sql).fetch(&self.pools);
-
-            let file = File::create(self.dir.join(format!("/tmp/{}.json", table.name)))
-                .expect("Unable to create file");
-            let mut file = BufWriter::new(file);
-            file.write_all("[".as_bytes()).expect("Unable to write data");
-
-            let mut initial = true;
-
-            while let Some(row) = rows.try_next().await? {
-                if !initial {
-                    file.write_all(",".as_bytes()).expect("Unable to write data");
-                } else {
-                    initial = false;
-                }
-
-                let mut key_value_map = HashMap::new();
-                for column in &table.columns {
-                    key_value_map.insert(
-                        column,
-                        mysql_value(schema.get(column).unwrap(), &row, column)?,
-                    );
-                }
-                file.write_all(serde_json::to_string(&key_value_map).unwrap().as_bytes())
-                    .expect("Unable to write data");
-            }
-
-            file.write_all("]".as_bytes()).expect("Unable to write data");
-            file.flush()?;
+        Ok(conn)
+    }
+
+    async fn end_snapshot(&self, conn: &mut PoolConnection<MySql>) -> anyhow::Result<()> {
+        if self.config.database.snapshot {
+            conn.execute("COMMIT").await?;
         }
 
         Ok(())
     }
 
-    async fn get_schema_for_table<'b>(
-        &self,
-        table_name: &'b str,
-    ) -> Result<HashMap<String, String>, sqlx::Error> {
-        let sql = format!("DESCRIBE {}", table_name);
+    async fn table_names(&self) -> anyhow::Result<Vec<String>> {
+        let sql = "SELECT TABLE_NAME FROM INFORMATION_SCHEMA.TABLES \
+                   WHERE TABLE_SCHEMA = ? AND TABLE_TYPE = 'BASE TABLE' ORDER BY TABLE_NAME";
+        let mut rows = sqlx::query(sql)
+            .bind(&self.config.database.database)
+            .fetch(&self.pools);
+
+        let mut table_names = Vec::new();
+        while let Some(row) = rows.try_next().await? {
+            table_names.push(row.try_get(0)?);
+        }
+
+        Ok(table_names)
+    }
+
+    async fn table_schema(&self, table: &str) -> anyhow::Result<IndexMap<String, String>> {
+        let sql = format!("DESCRIBE {}", mysql_identifier(table));
         let mut rows = sqlx::query(&sql).fetch(&self.pools);
 
-        let mut schema = HashMap::new();
+        let mut schema = IndexMap::new();
         while let Some(row) = rows.try_next().await? {
             let column_name: &str = row.try_get("Field")?;
             let data_type: &str = row.try_get("Type")?;
@@ -144,26 +178,154 @@ impl<'a> MySqlWriter<'a> {
 
         Ok(schema)
     }
+
+    async fn foreign_keys(&self) -> anyhow::Result<Vec<ForeignKey>> {
+        let sql = "SELECT kcu.CONSTRAINT_NAME, kcu.TABLE_NAME, kcu.COLUMN_NAME, \
+                   kcu.REFERENCED_TABLE_NAME, kcu.REFERENCED_COLUMN_NAME \
+                   FROM INFORMATION_SCHEMA.REFERENTIAL_CONSTRAINTS rc \
+                   JOIN INFORMATION_SCHEMA.KEY_COLUMN_USAGE kcu \
+                   ON kcu.CONSTRAINT_SCHEMA = rc.CONSTRAINT_SCHEMA \
+                   AND kcu.CONSTRAINT_NAME = rc.CONSTRAINT_NAME \
+                   AND kcu.TABLE_NAME = rc.TABLE_NAME \
+                   WHERE rc.CONSTRAINT_SCHEMA = ? \
+                   ORDER BY kcu.CONSTRAINT_NAME, kcu.TABLE_NAME, kcu.ORDINAL_POSITION";
+        let mut rows = sqlx::query(sql)
+            .bind(&self.config.database.database)
+            .fetch(&self.pools);
+
+        let mut columns = Vec::new();
+        while let Some(row) = rows.try_next().await? {
+            columns.push(ForeignKeyColumn {
+                constraint_name: row.try_get(0)?,
+                table: row.try_get(1)?,
+                column: row.try_get(2)?,
+                referenced_table: row.try_get(3)?,
+                referenced_column: row.try_get(4)?,
+            });
+        }
+
+        Ok(group_foreign_keys(columns))
+    }
+
+    async fn query_count(
+        &self,
+        conn: &mut PoolConnection<MySql>,
+        sql: &str,
+        values: &[String],
+    ) -> anyhow::Result<usize> {
+        let mut query = sqlx::query(sql);
+        for value in values {
+            query = query.bind(value);
+        }
+        let count: i64 = query.fetch_one(&mut **conn).await?.try_get(0)?;
+
+        Ok(count as usize)
+    }
+
+    async fn query_rows<'c>(
+        &'c self,
+        conn: &'c mut PoolConnection<MySql>,
+        table: &'c TableConfig,
+        sql: &'c str,
+        values: &'c [String],
+    ) -> anyhow::Result<TableRows<'c>> {
+        let (columns, types) = self.output_columns(conn, table, sql).await?;
+        let mut query = sqlx::query(sql);
+        for value in values {
+            query = query.bind(value);
+        }
+
+        let count = columns.len();
+        let rows = query
+            .fetch(&mut **conn)
+            .map_err(anyhow::Error::from)
+            .and_then(move |row| {
+                future::ready((0..count).map(|idx| mysql_value(&row, idx)).collect())
+            })
+            .boxed();
+        Ok(TableRows {
+            columns,
+            types,
+            rows,
+        })
+    }
 }
 
-fn mysql_value(
-    data_type: &str,
-    row: &MySqlRow,
-    column_name: &str,
-) -> Result<DataType, sqlx::Error> {
-    let (t, _) = data_type_regex(data_type);
-    let t = t.as_str();
-    match t {
-        "varchar" | "char" => Ok(DataType::String(row.try_get(&column_name)?)),
-        "int" => Ok(DataType::Int(row.try_get(&column_name)?)),
-        "bigint" => Ok(DataType::BigInt(row.try_get(&column_name)?)),
-        "float" => Ok(DataType::Float(row.try_get(&column_name)?)),
-        "double" => Ok(DataType::Double(row.try_get(&column_name)?)),
-        "boolean" => Ok(DataType::Bool(row.try_get(&column_name)?)),
-        "datetime" | "date" | "time" | _ => panic!("{} not yet implemented", data_type),
+/// Converts a value by the column type of the result set, so the query
+/// decides the type and not the schema of the table
+fn mysql_value(row: &MySqlRow, column_idx: usize) -> anyhow::Result<DataType> {
+    let column = &row.columns()[column_idx];
+    let data_type = column.type_info().name();
+    match data_type {
+        // type of literal nulls like `SELECT NULL AS x`
+        "NULL" => Ok(DataType::String(None)),
+        "CHAR" | "VARCHAR" | "TINYTEXT" | "TEXT" | "MEDIUMTEXT" | "LONGTEXT" | "ENUM" | "SET" => {
+            Ok(DataType::String(row.try_get(column_idx)?))
+        }
+        // sqlx does not consider json compatible with strings, but it is sent as text
+        "JSON" => Ok(DataType::json(row.try_get_unchecked(column_idx)?)?),
+        "BOOLEAN" => Ok(DataType::Bool(row.try_get(column_idx)?)),
+        "TINYINT" => {
+            let t: Option<i8> = row.try_get(column_idx)?;
+            Ok(DataType::Int(t.map(i32::from)))
+        }
+        "TINYINT UNSIGNED" => {
+            let t: Option<u8> = row.try_get(column_idx)?;
+            Ok(DataType::Int(t.map(i32::from)))
+        }
+        "SMALLINT" => {
+            let t: Option<i16> = row.try_get(column_idx)?;
+            Ok(DataType::Int(t.map(i32::from)))
+        }
+        "SMALLINT UNSIGNED" | "YEAR" => {
+            let t: Option<u16> = row.try_get(column_idx)?;
+            Ok(DataType::Int(t.map(i32::from)))
+        }
+        "INT" | "MEDIUMINT" => Ok(DataType::Int(row.try_get(column_idx)?)),
+        "INT UNSIGNED" | "MEDIUMINT UNSIGNED" => {
+            let t: Option<u32> = row.try_get(column_idx)?;
+            Ok(DataType::BigInt(t.map(i64::from)))
+        }
+        "BIGINT" => Ok(DataType::BigInt(row.try_get(column_idx)?)),
+        "BIGINT UNSIGNED" => {
+            let t: Option<u64> = row.try_get(column_idx)?;
+            Ok(DataType::unsigned(t))
+        }
+        "FLOAT" => Ok(DataType::Float(row.try_get(column_idx)?)),
+        "DOUBLE" => Ok(DataType::Double(row.try_get(column_idx)?)),
+        "DECIMAL" => Ok(DataType::Decimal(row.try_get(column_idx)?)),
+        "DATE" => Ok(DataType::Date(row.try_get(column_idx)?)),
+        "TIME" => Ok(DataType::Time(row.try_get(column_idx)?)),
+        "DATETIME" => Ok(DataType::DateTime(row.try_get(column_idx)?)),
+        "TIMESTAMP" => Ok(DataType::DateTimeUtc(row.try_get(column_idx)?)),
+        "BINARY" | "VARBINARY" | "TINYBLOB" | "BLOB" | "MEDIUMBLOB" | "LONGBLOB" => {
+            Ok(DataType::Binary(row.try_get(column_idx)?))
+        }
+        // bits are sent as big endian bytes of up to 64 bits
+        "BIT" => {
+            let t: Option<Vec<u8>> = row.try_get_unchecked(column_idx)?;
+            Ok(DataType::unsigned(t.map(|bytes| {
+                bytes.iter().fold(0u64, |bits, byte| bits << 8 | *byte as u64)
+            })))
+        }
+        // spatial columns of queries which are not selected as WKT are written
+        // in the internal format, the SRID followed by WKB
+        "GEOMETRY" => Ok(DataType::Binary(row.try_get_unchecked(column_idx)?)),
+        _ => bail!(
+            "column {} has the type {}, which is not supported",
+            column.name(),
+            data_type
+        ),
     }
 }
 
+/// Whether ST_AsText of the server takes the axis order, which is new in
+/// MySQL 8. Older servers and MariaDB write the coordinates as stored.
+fn has_axis_order(version: &str) -> bool {
+    let major = version.split('.').next().and_then(|major| major.parse::<u32>().ok());
+    !version.contains("MariaDB") && matches!(major, Some(major) if major >= 8)
+}
+
 async fn get_connection_pool(config: &Config) -> Result<Pool<MySql>, sqlx::Error> {
     let pool = MySqlPoolOptions::new()
         .max_connections(5)
@@ -173,29 +335,15 @@ async fn get_connection_pool(config: &Config) -> Result<Pool<MySql>, sqlx::Error
     Ok(pool)
 }
 
-fn data_type_regex(data_type: &str) -> (String, Option<i32>) {
-    let reg = Regex::new(r"([a-z]+)(?:\((\d)*\))*").unwrap();
-    let matches = reg.captures(data_type).unwrap();
-    let t = &matches[1];
-
-    (
-        t.to_owned(),
-        matches
-            .get(2)
-            .map(|elem| elem.as_str().parse::<i32>().unwrap()),
-    )
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_data_type_regex() {
-        assert_eq!(("int".to_owned(), None), data_type_regex("int"));
-        assert_eq!(
-            ("varchar".to_owned(), Some(5)),
-            data_type_regex("varchar(5)")
-        );
+    fn test_has_axis_order() {
+        assert!(has_axis_order("8.0.27"));
+        assert!(has_axis_order("8.0.27-log"));
+        assert!(!has_axis_order("5.7.36"));
+        assert!(!has_axis_order("10.6.5-MariaDB"));
     }
 }
@@ -0,0 +1,521 @@
+use anyhow::bail;
+use indexmap::IndexMap;
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::config::TableConfig;
+use crate::manifest::ManifestFile;
+use crate::output::{FileChecksum, OutputFile};
+use crate::writer::{ExistingFiles, WriterOptions};
+
+/// Partitions with an open file, each holding a file descriptor and a
+/// compressor
+const MAX_OPEN_PARTITIONS: usize = 64;
+
+/// Output of a table, with a [`TableFile`] in a directory per partition like
+/// `orders/region=eu/part.json`. If more than [`MAX_OPEN_PARTITIONS`] are
+/// written at the same time, the least recently used one is finished and its
+/// next rows go to a new part like `orders/region=eu/part.part-0002.json`.
+pub struct TableFiles<'a> {
+    options: &'a WriterOptions,
+    file_name: String,
+    header: String,
+    separator: &'static str,
+    footer: String,
+    empty_frame: bool,
+    /// Open files by partition, the least recently used first
+    files: IndexMap<String, TableFile<'a>>,
+    /// Last part of the partitions whose files were finished
+    closed_parts: HashMap<String, usize>,
+    /// Parts of the finished files
+    finished: Vec<ManifestFile>,
+}
+
+impl<'a> TableFiles<'a> {
+    /// Files without rows get the header and footer only with `empty_frame`,
+    /// else they are empty, as an `INSERT` without values is no valid SQL
+    pub fn create(
+        options: &'a WriterOptions,
+        table: &TableConfig,
+        file_name: String,
+        header: String,
+        separator: &'static str,
+        footer: String,
+        empty_frame: bool,
+    ) -> anyhow::Result<TableFiles<'a>> {
+        let mut files = TableFiles {
+            options,
+            file_name,
+            header,
+            separator,
+            footer,
+            empty_frame,
+            files: IndexMap::new(),
+            closed_parts: HashMap::new(),
+            finished: Vec::new(),
+        };
+        // tables without rows still get a file, unless nothing says which partition it is in
+        if table.partition_by.is_empty() {
+            files.file("")?;
+        }
+
+        Ok(files)
+    }
+
+    /// Checks the outputs of all tables before anything is written, and
+    /// returns the tables with their file names which are written. An
+    /// existing output is an error, unless existing files are overwritten or
+    /// skipped. The output of a partitioned table is its directory, else its
+    /// first file.
+    pub fn check_existing<'t>(
+        options: &WriterOptions,
+        outputs: Vec<(&'t TableConfig, String)>,
+    ) -> anyhow::Result<Vec<(&'t TableConfig, String)>> {
+        if options.pipe.is_some() || options.existing_files == ExistingFiles::Overwrite {
+            return Ok(outputs);
+        }
+
+        let mut written = Vec::new();
+        let mut existing = Vec::new();
+        for (table, file_name) in outputs {
+            let exists = if table.partition_by.is_empty() {
+                let name = options.output_name(&part_file_name(options, &file_name, 1));
+                options.output_exists(&name, false)?
+            } else {
+                options.output_exists(split_extension(&file_name).0, true)?
+            };
+            if !exists {
+                written.push((table, file_name));
+            } else if options.existing_files == ExistingFiles::Skip {
+                eprintln!("skipping table {}, its output exists", table.name);
+            } else {
+                existing.push(table.name.as_str());
+            }
+        }
+        if !existing.is_empty() {
+            bail!(
+                "output of tables {} exists, use --overwrite or --skip-existing",
+                existing.join(", ")
+            );
+        }
+
+        Ok(written)
+    }
+
+    pub fn write_row(&mut self, partition: &str, row: &str) -> anyhow::Result<()> {
+        self.file(partition)?.write_row(row)
+    }
+
+    /// Files of the table for the manifest. When overwriting, files of an
+    /// earlier run which were not replaced are removed afterwards, like parts
+    /// beyond the last one or partitions which have no rows anymore.
+    pub fn finish(self) -> anyhow::Result<Vec<ManifestFile>> {
+        let mut files = self.finished;
+        for (_, file) in self.files {
+            files.extend(file.finish()?);
+        }
+
+        if self.options.pipe.is_none() && self.options.existing_files == ExistingFiles::Overwrite {
+            let stem = split_extension(&self.file_name).0;
+            for name in self.options.output_names(stem)? {
+                if is_output_of(self.options, &self.file_name, &name)
+                    && !files.iter().any(|file| file.path == name)
+                {
+                    self.options.remove_output(&name)?;
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn file(&mut self, partition: &str) -> anyhow::Result<&mut TableFile<'a>> {
+        match self.files.get_index_of(partition) {
+            Some(index) if index + 1 < self.files.len() => {
+                let (partition, file) = self.files.shift_remove_index(index).unwrap();
+                self.files.insert(partition, file);
+            }
+            Some(_) => {}
+            None => {
+                if self.files.len() >= MAX_OPEN_PARTITIONS {
+                    let (partition, file) = self.files.shift_remove_index(0).unwrap();
+                    self.closed_parts.insert(partition, file.part);
+                    self.finished.extend(file.finish()?);
+                }
+                let file = TableFile::create(
+                    self.options,
+                    partition_file_name(&self.file_name, partition),
+                    self.header.clone(),
+                    self.separator,
+                    self.footer.clone(),
+                    self.empty_frame,
+                    self.closed_parts.get(partition).map_or(1, |part| part + 1),
+                )?;
+                self.files.insert(partition.to_string(), file);
+            }
+        }
+
+        Ok(&mut self.files[partition])
+    }
+}
+
+/// Whether `name` is a file written for `file_name`, as a whole, as a part or
+/// in the directory of a partition like `region=eu`
+fn is_output_of(options: &WriterOptions, file_name: &str, name: &str) -> bool {
+    let (stem, extension) = split_extension(file_name);
+    let rest = match name.strip_prefix(stem) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let part = rest
+        .strip_prefix(".part-")
+        .and_then(|part| part.strip_suffix(&options.output_name(extension)));
+
+    name == options.output_name(file_name)
+        || part.is_some_and(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+        || rest
+            .strip_prefix('/')
+            .is_some_and(|path| path.split('/').next().unwrap_or_default().contains('='))
+}
+
+/// Files of partitions are named `part` in a directory named after the table
+fn partition_file_name(file_name: &str, partition: &str) -> String {
+    if partition.is_empty() {
+        return file_name.to_string();
+    }
+
+    let (stem, extension) = split_extension(file_name);
+    format!("{}/{}/part{}", stem, partition, extension)
+}
+
+/// Stem and extension of the last component of a file name, the extension
+/// starting with its dot
+fn split_extension(file_name: &str) -> (&str, &str) {
+    let name_start = file_name.rfind('/').map_or(0, |slash| slash + 1);
+    match file_name[name_start..].rfind('.') {
+        Some(dot) => file_name.split_at(name_start + dot),
+        None => (file_name, ""),
+    }
+}
+
+/// Output of a table, split into numbered part files like
+/// `orders.part-0001.json` if a limit of rows or bytes per file is set. Each
+/// part starts with the header and ends with the footer, so it can be loaded
+/// on its own.
+pub struct TableFile<'a> {
+    options: &'a WriterOptions,
+    file_name: String,
+    header: String,
+    separator: &'static str,
+    footer: String,
+    empty_frame: bool,
+    file: OutputFile,
+    /// Name of the current part
+    part_name: String,
+    part: usize,
+    rows: usize,
+    /// Bytes of the current part before compression
+    bytes: usize,
+    /// Finished parts
+    files: Vec<ManifestFile>,
+}
+
+impl<'a> TableFile<'a> {
+    pub fn create(
+        options: &'a WriterOptions,
+        file_name: String,
+        header: String,
+        separator: &'static str,
+        footer: String,
+        empty_frame: bool,
+        part: usize,
+    ) -> anyhow::Result<TableFile<'a>> {
+        let part_name = part_file_name(options, &file_name, part);
+        let file = options.create_file(&part_name)?;
+
+        Ok(TableFile {
+            options,
+            file_name,
+            header,
+            separator,
+            footer,
+            empty_frame,
+            file,
+            part_name,
+            part,
+            rows: 0,
+            bytes: 0,
+            files: Vec::new(),
+        })
+    }
+
+    pub fn write_row(&mut self, row: &str) -> anyhow::Result<()> {
+        if self.rows > 0 && self.is_full(row.len()) {
+            self.next_part()?;
+        }
+
+        if self.rows > 0 {
+            self.write(self.separator)?;
+        } else {
+            self.write_header()?;
+        }
+        self.write(row)?;
+        self.rows += 1;
+
+        Ok(())
+    }
+
+    /// Parts of the table for the manifest
+    pub fn finish(mut self) -> anyhow::Result<Vec<ManifestFile>> {
+        if self.rows == 0 && self.empty_frame {
+            self.write_header()?;
+        }
+        if self.rows > 0 || self.empty_frame {
+            let footer = std::mem::take(&mut self.footer);
+            self.write(&footer)?;
+        }
+        let checksum = self.file.finish()?;
+        let part = manifest_file(self.options, &self.part_name, self.rows, checksum);
+        self.files.push(part);
+
+        Ok(self.files)
+    }
+
+    /// A row does not go into the current part if it has the maximum number
+    /// of rows, or if the row would make it larger than the maximum size
+    fn is_full(&self, row_len: usize) -> bool {
+        let rows_reached = self
+            .options
+            .max_rows_per_file
+            .is_some_and(|max_rows| self.rows >= max_rows);
+        let bytes_reached = self.options.max_bytes_per_file.is_some_and(|max_bytes| {
+            self.bytes + self.separator.len() + row_len + self.footer.len() > max_bytes
+        });
+
+        rows_reached || bytes_reached
+    }
+
+    fn next_part(&mut self) -> anyhow::Result<()> {
+        let footer = self.footer.clone();
+        self.write(&footer)?;
+
+        self.part += 1;
+        let part_name = part_file_name(self.options, &self.file_name, self.part);
+        let file = self.options.create_file(&part_name)?;
+        let checksum = std::mem::replace(&mut self.file, file).finish()?;
+        let part = manifest_file(self.options, &self.part_name, self.rows, checksum);
+        self.files.push(part);
+        self.part_name = part_name;
+        self.rows = 0;
+        self.bytes = 0;
+
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> anyhow::Result<()> {
+        let header = self.header.clone();
+        self.write(&header)
+    }
+
+    fn write(&mut self, text: &str) -> anyhow::Result<()> {
+        self.file.write_all(text.as_bytes())?;
+        self.bytes += text.len();
+
+        Ok(())
+    }
+}
+
+fn manifest_file(
+    options: &WriterOptions,
+    part_name: &str,
+    rows: usize,
+    checksum: FileChecksum,
+) -> ManifestFile {
+    ManifestFile {
+        path: options.output_name(part_name),
+        rows,
+        bytes: checksum.bytes,
+        sha256: checksum.sha256,
+    }
+}
+
+/// Inserts the part number before the extension if the output is split or the
+/// part continues a finished one
+fn part_file_name(options: &WriterOptions, file_name: &str, part: usize) -> String {
+    if part == 1 && options.max_rows_per_file.is_none() && options.max_bytes_per_file.is_none() {
+        return file_name.to_string();
+    }
+
+    let (stem, extension) = split_extension(file_name);
+    format!("{}.part-{:04}{}", stem, part, extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::writer::tests::options;
+    use std::fs;
+
+    #[test]
+    fn test_parts_are_complete_files() {
+        let dir = std::env::temp_dir().join("db-extractor-table-file-test");
+        fs::create_dir_all(&dir).unwrap();
+        let options = options(&dir, Some(2), ExistingFiles::Overwrite);
+
+        let mut file = TableFile::create(
+            &options,
+            "orders.json".to_string(),
+            "[".to_string(),
+            ",",
+            "]".to_string(),
+            true,
+            1,
+        )
+        .unwrap();
+        for id in 1..=3 {
+            file.write_row(&format!(r#"{{"id":{}}}"#, id)).unwrap();
+        }
+        let files = file.finish().unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].path, "orders.part-0002.json");
+        assert_eq!(files[1].rows, 1);
+        let part = |number| fs::read_to_string(dir.join(format!("orders.part-{}.json", number)));
+        assert_eq!(part("0001").unwrap(), r#"[{"id":1},{"id":2}]"#);
+        assert_eq!(part("0002").unwrap(), r#"[{"id":3}]"#);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_files_without_rows_have_a_frame_if_it_is_valid() {
+        let dir = std::env::temp_dir().join("db-extractor-empty-file-test");
+        fs::create_dir_all(&dir).unwrap();
+        let options = options(&dir, None, ExistingFiles::Overwrite);
+        let create = |file_name: &str, header: &str, footer: &str, empty_frame| {
+            TableFile::create(
+                &options,
+                file_name.to_string(),
+                header.to_string(),
+                ",",
+                footer.to_string(),
+                empty_frame,
+                1,
+            )
+            .unwrap()
+            .finish()
+            .unwrap()
+        };
+
+        let files = create("orders.json", "[", "]", true);
+        assert_eq!(files[0].rows, 0);
+        assert_eq!(fs::read_to_string(dir.join("orders.json")).unwrap(), "[]");
+        create("orders.sql", "INSERT INTO orders (id) VALUES ", ";", false);
+        assert_eq!(fs::read_to_string(dir.join("orders.sql")).unwrap(), "");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_least_recently_used_partition_is_continued_in_new_part() {
+        let dir = std::env::temp_dir().join("db-extractor-partitions-test");
+        fs::create_dir_all(&dir).unwrap();
+        let options = options(&dir, None, ExistingFiles::Overwrite);
+        let table: TableConfig = serde_json::from_value(serde_json::json!({
+            "name": "orders",
+            "partition_by": [{"column": "region"}]
+        }))
+        .unwrap();
+
+        let mut files = TableFiles::create(
+            &options,
+            &table,
+            "orders.json".to_string(),
+            "[".to_string(),
+            ",",
+            "]".to_string(),
+            true,
+        )
+        .unwrap();
+        for region in (0..=MAX_OPEN_PARTITIONS).chain(0..1) {
+            let partition = format!("region={}", region);
+            files.write_row(&partition, &format!(r#"{{"region":{}}}"#, region)).unwrap();
+        }
+        let files = files.finish().unwrap();
+
+        assert_eq!(files.len(), MAX_OPEN_PARTITIONS + 2);
+        let part = |name| fs::read_to_string(dir.join("orders/region=0").join(name)).unwrap();
+        assert_eq!(part("part.json"), r#"[{"region":0}]"#);
+        assert_eq!(part("part.part-0002.json"), r#"[{"region":0}]"#);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_existing_output_is_skipped_or_fails() {
+        let dir = std::env::temp_dir().join("db-extractor-existing-test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("orders.json"), "[]").unwrap();
+        let table = |name: &str| TableConfig {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        let (orders, customers) = (table("orders"), table("customers"));
+        let outputs = || {
+            vec![
+                (&customers, "customers.json".to_string()),
+                (&orders, "orders.json".to_string()),
+            ]
+        };
+
+        let skip = options(&dir, None, ExistingFiles::Skip);
+        let written = TableFiles::check_existing(&skip, outputs()).unwrap();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].1, "customers.json");
+        let fail = options(&dir, None, ExistingFiles::Fail);
+        assert!(TableFiles::check_existing(&fail, outputs()).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_overwrite_removes_stale_parts_and_partitions() {
+        let dir = std::env::temp_dir().join("db-extractor-overwrite-test");
+        fs::create_dir_all(dir.join("orders/region=us")).unwrap();
+        fs::create_dir_all(dir.join("orders/notes")).unwrap();
+        for name in &[
+            "orders.part-0004.json",
+            "orders/region=us/part.json",
+            "orders/notes/readme.txt",
+            "orders_items.json",
+        ] {
+            fs::write(dir.join(name), "[]").unwrap();
+        }
+        let options = options(&dir, None, ExistingFiles::Overwrite);
+        let table = TableConfig {
+            name: "orders".to_string(),
+            ..Default::default()
+        };
+
+        let mut files = TableFiles::create(
+            &options,
+            &table,
+            "orders.json".to_string(),
+            "[".to_string(),
+            ",",
+            "]".to_string(),
+            true,
+        )
+        .unwrap();
+        files.write_row("", "{}").unwrap();
+        files.finish().unwrap();
+
+        assert!(dir.join("orders.json").exists());
+        assert!(!dir.join("orders.part-0004.json").exists());
+        assert!(!dir.join("orders/region=us").exists());
+        assert!(dir.join("orders/notes/readme.txt").exists());
+        assert!(dir.join("orders_items.json").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}